@@ -1,3 +1,8 @@
 fn main() {
+    // surfaced by --version
+    println!(
+        "cargo:rustc-env=TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
     embed_manifest::embed_manifest_file("app.manifest").expect("embed manifest");
 }
@@ -0,0 +1,221 @@
+// Where captured pixels come from: the screen via GDI, a window's DWM thumbnail, or a
+// synthetic image in tests.
+
+use windows::Win32::Foundation::HWND;
+use windows::Win32::Graphics::Gdi;
+
+#[cfg(test)]
+use crate::{BitmapGuard, Session};
+use crate::{Options, Result, capture_region};
+
+/// Source of captured frames. Everything after the capture (post-processing, encoding,
+/// saving) is shared, so swapping the backend exercises the whole pipeline.
+pub(crate) trait CaptureBackend {
+    /// Capture `rect` (x, y, w, h) into a new DIB section that the caller must delete.
+    fn capture(&self, rect: (i32, i32, i32, i32), options: &Options) -> Result<Gdi::HBITMAP>;
+}
+
+/// The real backend: BitBlt from the screen DC.
+pub(crate) struct GdiBackend;
+
+impl CaptureBackend for GdiBackend {
+    fn capture(
+        &self,
+        (x, y, w, h): (i32, i32, i32, i32),
+        options: &Options,
+    ) -> Result<Gdi::HBITMAP> {
+        capture_region(x, y, w, h, options)
+    }
+}
+
+/// --dwm-thumbnail: the DWM thumbnail of one window, composed into a helper window laid over
+/// it and then copied from the screen. Rectangles are screen coordinates within the window.
+pub(crate) struct DwmThumbnailBackend {
+    pub(crate) hwnd: HWND,
+    pub(crate) client_only: bool,
+}
+
+impl CaptureBackend for DwmThumbnailBackend {
+    fn capture(&self, rect: (i32, i32, i32, i32), options: &Options) -> Result<Gdi::HBITMAP> {
+        crate::dwm::capture_thumbnail(self.hwnd, self.client_only, rect, options)
+    }
+}
+
+/// Test backend: a gradient (blue rises left to right, green top to bottom, red fixed at 128)
+/// the size of the requested rectangle, with no display involved.
+#[cfg(test)]
+pub(crate) struct FakeBackend;
+
+#[cfg(test)]
+impl CaptureBackend for FakeBackend {
+    fn capture(
+        &self,
+        (_, _, w, h): (i32, i32, i32, i32),
+        options: &Options,
+    ) -> Result<Gdi::HBITMAP> {
+        let (hbmp, _) = crate::make_dib_section(w, h, Gdi::HDC::default(), options.bit_depth)?;
+        let mut bits = crate::pixels::dib_bits(hbmp)?;
+        let (px_size, stride) = ((bits.bpp / 8) as usize, bits.stride);
+        let buf = bits.bytes_mut();
+        for y in 0..h as usize {
+            let row = h as usize - 1 - y; // bottom-up
+            for x in 0..w as usize {
+                let px = &mut buf[row * stride + x * px_size..][..3];
+                px[0] = (x * 255 / (w as usize - 1).max(1)) as u8;
+                px[1] = (y * 255 / (h as usize - 1).max(1)) as u8;
+                px[2] = 128;
+            }
+        }
+        Ok(hbmp)
+    }
+}
+
+/// A session (GDI+ started) whose captures come from [`FakeBackend`].
+#[cfg(test)]
+pub(crate) fn fake_session() -> Session {
+    Session::with_backend(Box::new(FakeBackend)).unwrap()
+}
+
+/// A `w`x`h` [`FakeBackend`] capture, deleted when the guard drops.
+#[cfg(test)]
+pub(crate) fn fake_image(w: i32, h: i32, options: &Options) -> BitmapGuard {
+    BitmapGuard(FakeBackend.capture((0, 0, w, h), options).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImgGuard, SnapshotError, clsid_for_extension, image_size, pixels, wide};
+    use std::path::PathBuf;
+    use windows::Win32::Graphics::GdiPlus;
+    use windows::core::PCWSTR;
+
+    fn session() -> Session {
+        fake_session()
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gdip_snapshot_test_{}_{name}", std::process::id()))
+    }
+
+    fn load(path: &PathBuf) -> ImgGuard {
+        let wname = wide(path);
+        let mut img = std::ptr::null_mut();
+        let status = unsafe { GdiPlus::GdipLoadImageFromFile(PCWSTR(wname.as_ptr()), &mut img) };
+        assert_eq!(status, GdiPlus::Ok);
+        ImgGuard(img)
+    }
+
+    #[test]
+    fn webp_quality_shrinks_output_when_the_codec_is_installed() {
+        let session = session();
+        if let Err(SnapshotError::NoEncoder(_)) = clsid_for_extension("webp") {
+            return; // stock GDI+ has no WebP encoder
+        }
+        let encode = |quality| {
+            let options = Options {
+                quality: Some(quality),
+                ..Options::default()
+            };
+            session
+                .capture_encoded((0, 0, 256, 256), "webp", &options)
+                .unwrap()
+        };
+        let (low, high) = (encode(10), encode(95));
+        assert_eq!(&low.bytes[8..12], b"WEBP");
+        assert!(low.bytes.len() < high.bytes.len());
+    }
+
+    #[test]
+    fn in_memory_encode_reports_the_encoder_mime_type() {
+        let encoded = session()
+            .capture_encoded((0, 0, 16, 16), "png", &Options::default())
+            .unwrap();
+        assert_eq!(encoded.mime_type, "image/png");
+        assert!(encoded.bytes.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert!(
+            encoded
+                .data_uri()
+                .starts_with("data:image/png;base64,iVBORw0KGgo")
+        );
+    }
+
+    #[test]
+    fn png_capture_round_trips_through_the_encoder() {
+        let session = session();
+        let path = temp_file("round_trip.png");
+        let file = path.to_str().unwrap();
+        session
+            .capture(0, 0, 64, 32, file, &Options::default())
+            .unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let img = load(&path);
+        assert_eq!(image_size(&img).unwrap(), (64, 32));
+        // the gradient survives: top-left is dark blue/green, bottom-right bright
+        pixels::lock_bits(&img, false, |buf, w, h, stride| {
+            assert_eq!(&buf[..3], &[0, 0, 128]);
+            let last = (h as usize - 1) * stride + (w as usize - 1) * 4;
+            assert_eq!(&buf[last..last + 3], &[255, 255, 128]);
+        })
+        .unwrap();
+        drop(img);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn scale_resizes_the_saved_image() {
+        let session = session();
+        let path = temp_file("scaled.bmp");
+        let options = Options {
+            scale: Some(50),
+            ..Default::default()
+        };
+        session
+            .capture(0, 0, 200, 100, path.to_str().unwrap(), &options)
+            .unwrap();
+        let img = load(&path);
+        assert_eq!(image_size(&img).unwrap(), (100, 50));
+        drop(img);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn saves_to_long_non_ascii_paths() {
+        let session = session();
+        // well past MAX_PATH, with Japanese directory and file names
+        let mut dir = temp_file("長いパス");
+        for i in 0..12 {
+            dir.push(format!("スクリーンショット保存先フォルダ_{i:02}"));
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["画面.png", "画面.JPG"] {
+            let path = dir.join(name);
+            let file = path.to_str().unwrap();
+            assert!(file.encode_utf16().count() > 260);
+            session
+                .capture(0, 0, 16, 16, file, &Options::default())
+                .unwrap();
+            assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        }
+        let _ = std::fs::remove_dir_all(temp_file("長いパス"));
+    }
+
+    #[test]
+    fn every_installed_encoder_round_trips() {
+        let checks = session().selftest().unwrap();
+        assert!(checks.iter().any(|c| c.format == "png"));
+        for check in checks {
+            assert!(check.result.is_ok(), "{}: {:?}", check.format, check.result);
+        }
+    }
+
+    #[test]
+    fn bench_runs_against_the_fake_backend() {
+        let report = session()
+            .bench((0, 0, 64, 64), 3, "png", &Options::default())
+            .unwrap();
+        assert_eq!(report.frames, 3);
+        assert!(report.encoded_bytes > 0);
+    }
+}
@@ -0,0 +1,108 @@
+// Capture/encode throughput measurement for --bench.
+
+use std::time::{Duration, Instant};
+
+use windows::Win32::Graphics::Gdi;
+
+use crate::backend::CaptureBackend;
+use crate::{
+    Options, Result, clsid_for_extension, encode_image, encoder_setting, render_for_format,
+};
+
+/// Timings for one phase, in milliseconds per frame.
+pub struct PhaseStats {
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub max_ms: f64,
+    pub avg_ms: f64,
+    pub fps: f64,
+}
+
+impl PhaseStats {
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let total: Duration = samples.iter().sum();
+        let avg_ms = ms(total) / samples.len() as f64;
+        Self {
+            min_ms: ms(samples[0]),
+            median_ms: ms(samples[samples.len() / 2]),
+            max_ms: ms(samples[samples.len() - 1]),
+            avg_ms,
+            fps: if avg_ms > 0.0 {
+                1000.0 / avg_ms
+            } else {
+                f64::INFINITY
+            },
+        }
+    }
+}
+
+/// Result of [`crate::Session::bench`].
+pub struct BenchReport {
+    pub frames: u32,
+    /// BitBlt into a DIB section only.
+    pub capture: PhaseStats,
+    /// Post-processing plus encoding to memory.
+    pub encode: PhaseStats,
+    /// Size of the last encoded frame.
+    pub encoded_bytes: usize,
+}
+
+fn delete(hbmp: Gdi::HBITMAP) {
+    unsafe {
+        let _ = Gdi::DeleteObject(hbmp.into());
+    }
+}
+
+pub(crate) fn run(
+    backend: &dyn CaptureBackend,
+    rect: (i32, i32, i32, i32),
+    frames: u32,
+    ext: &str,
+    options: &Options,
+) -> Result<BenchReport> {
+    let frames = frames.max(1);
+    let clsid = clsid_for_extension(ext)?;
+
+    let mut capture = Vec::with_capacity(frames as usize);
+    for _ in 0..frames {
+        let started = Instant::now();
+        let hbmp = backend.capture(rect, options)?;
+        capture.push(started.elapsed());
+        delete(hbmp);
+    }
+
+    // encode the same frame repeatedly so capture cost stays out of the numbers. the same
+    // rendering as a save, warning (if at all) on the first frame only
+    let repeat = Options {
+        quiet: true,
+        verbose: false,
+        ..options.clone()
+    };
+    let hbmp = backend.capture(rect, options)?;
+    let mut encode = Vec::with_capacity(frames as usize);
+    let mut encoded_bytes = 0;
+    for i in 0..frames {
+        let options = if i == 0 { options } else { &repeat };
+        let started = Instant::now();
+        let bytes = render_for_format(hbmp, ext, options, None)
+            .and_then(|(img, _)| encode_image(&img, &clsid, encoder_setting(ext, options)));
+        encode.push(started.elapsed());
+        match bytes {
+            Ok(b) => encoded_bytes = b.len(),
+            Err(e) => {
+                delete(hbmp);
+                return Err(e);
+            }
+        }
+    }
+    delete(hbmp);
+
+    Ok(BenchReport {
+        frames,
+        capture: PhaseStats::from_samples(capture),
+        encode: PhaseStats::from_samples(encode),
+        encoded_bytes,
+    })
+}
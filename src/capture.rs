@@ -0,0 +1,331 @@
+// Builder-style entry point: CaptureOptions collects the target, the output and the
+// post-processing settings, build() checks them, and capture() (or
+// Session::capture_request, for many captures) resolves the target and runs the capture. The
+// CLI builds its captures the same way. New settings become new builder methods, so callers
+// written against an older version keep compiling.
+
+use crate::backend::{CaptureBackend, GdiBackend};
+use crate::{
+    BitDepth, EncodedImage, LabelPos, Options, Result, ScreenMode, Session, SnapshotError,
+    WindowSelector, capture_with, encode_hbitmap, find_window, monitor_scale, save_hbitmap,
+    scale_rect, screen_rect, sub_rect, window_rect,
+};
+
+type Rect = (i32, i32, i32, i32); // x, y, w, h
+
+/// What a [`capture`] covers.
+#[derive(Clone)]
+pub enum CaptureTarget {
+    /// A rectangle in virtual-desktop coordinates (physical pixels unless
+    /// [`CaptureOptions::dip`] is set). Parts off the desktop come back black.
+    Rect(i32, i32, i32, i32),
+    /// A whole monitor or the virtual desktop.
+    Screen(ScreenMode),
+    /// A window's frame, or its client area with `client_only`.
+    Window {
+        selector: WindowSelector,
+        client_only: bool,
+    },
+}
+
+/// Builder for a [`CaptureRequest`]. Starts out as a capture of the primary monitor, encoded
+/// as PNG in memory, with default [`Options`].
+#[derive(Clone)]
+pub struct CaptureOptions {
+    target: CaptureTarget,
+    crop: Option<Rect>,
+    dip: bool,
+    file: Option<String>,
+    check_black: bool,
+    options: Options,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            target: CaptureTarget::Screen(ScreenMode::Primary),
+            crop: None,
+            dip: false,
+            file: None,
+            check_black: false,
+            options: Options::default(),
+        }
+    }
+}
+
+impl CaptureOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from these post-processing and save settings instead of the defaults, for
+    /// settings without a builder method of their own; the setters then change single ones.
+    pub fn from_options(options: Options) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Capture this target instead of the primary monitor.
+    pub fn target(mut self, target: CaptureTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Capture the rectangle (x, y, w, h).
+    pub fn rect(self, x: i32, y: i32, w: i32, h: i32) -> Self {
+        self.target(CaptureTarget::Rect(x, y, w, h))
+    }
+
+    /// Capture a whole monitor or the virtual desktop.
+    pub fn screen(self, mode: ScreenMode) -> Self {
+        self.target(CaptureTarget::Screen(mode))
+    }
+
+    /// Capture a window, or only its client area.
+    pub fn window(self, selector: WindowSelector, client_only: bool) -> Self {
+        self.target(CaptureTarget::Window {
+            selector,
+            client_only,
+        })
+    }
+
+    /// Keep only this part (x, y, w, h) of the target, relative to its top-left corner; like
+    /// `--window-region`, negative x/y anchor from the right/bottom edge.
+    pub fn crop(mut self, x: i32, y: i32, w: i32, h: i32) -> Self {
+        self.crop = Some((x, y, w, h));
+        self
+    }
+
+    /// Read a [`CaptureTarget::Rect`] in device-independent pixels of the monitor it is on.
+    pub fn dip(mut self, dip: bool) -> Self {
+        self.dip = dip;
+        self
+    }
+
+    /// Save to this file (the encoder follows the extension, or [`Self::format`]) instead of
+    /// encoding in memory.
+    pub fn file(mut self, filename: impl Into<String>) -> Self {
+        self.file = Some(filename.into());
+        self
+    }
+
+    /// Encoder extension ("png", "jpg", ...); overrides the file's, and picks the in-memory
+    /// encoding (default PNG).
+    pub fn format(mut self, ext: impl Into<String>) -> Self {
+        self.options.format = Some(ext.into());
+        self
+    }
+
+    /// Draw the mouse pointer into the capture.
+    pub fn cursor(mut self, include: bool) -> Self {
+        self.options.include_cursor = include;
+        self
+    }
+
+    /// Lossy encoder quality, 0..=100 (JPEG, WebP, HEIF).
+    pub fn quality(mut self, quality: u32) -> Self {
+        self.options.quality = Some(quality);
+        self
+    }
+
+    /// PNG compression level, 0 (fastest) ..= 9 (smallest); not every GDI+ version honors it.
+    pub fn png_compression(mut self, level: u32) -> Self {
+        self.options.png_compression = Some(level);
+        self
+    }
+
+    /// Resize the output to this percentage.
+    pub fn scale(mut self, percent: u32) -> Self {
+        self.options.scale = Some(percent);
+        self
+    }
+
+    /// Shrink the output so neither side exceeds `px`; smaller images are left alone.
+    pub fn max_dimension(mut self, px: u32) -> Self {
+        self.options.max_dimension = Some(px);
+        self
+    }
+
+    /// Stamp `text` into a corner of the image.
+    pub fn label(mut self, text: impl Into<String>, pos: LabelPos) -> Self {
+        self.options.label = Some(text.into());
+        self.options.label_pos = pos;
+        self
+    }
+
+    pub fn bit_depth(mut self, depth: BitDepth) -> Self {
+        self.options.bit_depth = depth;
+        self
+    }
+
+    /// Check whether the capture came back entirely black (see [`CaptureResult::all_black`]),
+    /// as a locked desktop or a window that blocks capture gives. Off by default, since it reads
+    /// the pixels back (all of them, for a black capture).
+    pub fn check_black(mut self, check: bool) -> Self {
+        self.check_black = check;
+        self
+    }
+
+    /// Check the settings and produce the request [`capture`] takes.
+    pub fn build(self) -> Result<CaptureRequest> {
+        let invalid = |msg: &str| Err(SnapshotError::InvalidOption(msg.into()));
+        if self.options.quality.is_some_and(|q| q > 100) {
+            return invalid("quality must be between 0 and 100");
+        }
+        if self.options.png_compression.is_some_and(|c| c > 9) {
+            return invalid("png_compression must be between 0 and 9");
+        }
+        if self.options.scale == Some(0) || self.options.max_dimension == Some(0) {
+            return invalid("scale and max_dimension must be > 0");
+        }
+        if self.dip && !matches!(self.target, CaptureTarget::Rect(..)) {
+            return invalid("dip applies to a rectangle target");
+        }
+        if let CaptureTarget::Rect(_, _, w, h) = self.target
+            && (w <= 0 || h <= 0)
+        {
+            return Err(SnapshotError::InvalidRegion(format!(
+                "rectangle {w}x{h} is empty"
+            )));
+        }
+        Ok(CaptureRequest(self))
+    }
+}
+
+/// Checked capture settings from [`CaptureOptions::build`].
+#[derive(Clone)]
+pub struct CaptureRequest(CaptureOptions);
+
+impl CaptureRequest {
+    /// The post-processing and save settings the capture runs with.
+    pub fn options(&self) -> &Options {
+        &self.0.options
+    }
+}
+
+/// What [`capture`] produced.
+pub struct CaptureResult {
+    /// The screen rectangle that was captured, in physical pixels (before scaling).
+    pub rect: (i32, i32, i32, i32),
+    /// The file that was written, if the request named one.
+    pub file: Option<String>,
+    /// The encoded image, for requests without a file.
+    pub image: Option<EncodedImage>,
+    /// Whether every captured pixel was black, with [`CaptureOptions::check_black`]; always
+    /// false without it.
+    pub all_black: bool,
+}
+
+// the target's screen rectangle, cropped.
+fn resolve(request: &CaptureOptions) -> Result<Rect> {
+    let rect = match &request.target {
+        &CaptureTarget::Rect(x, y, w, h) if request.dip => {
+            let (scale, (mx, my, _, _)) = monitor_scale((x, y, w, h))?;
+            scale_rect((x, y, w, h), (mx, my), scale, 100)
+        }
+        &CaptureTarget::Rect(x, y, w, h) => (x, y, w, h),
+        &CaptureTarget::Screen(mode) => screen_rect(mode)?,
+        CaptureTarget::Window {
+            selector,
+            client_only,
+        } => window_rect(find_window(selector)?, *client_only)?,
+    };
+    match request.crop {
+        Some(crop) => sub_rect(rect, crop),
+        None => Ok(rect),
+    }
+}
+
+/// Capture what `request` describes and save it to its file, or encode it in memory if it has
+/// none. GDI+ is started and shut down for the duration of the call, and not at all for a file
+/// with `no_gdiplus`.
+pub fn capture(request: &CaptureRequest) -> Result<CaptureResult> {
+    let inner = &request.0;
+    if let (Some(_), true) = (&inner.file, inner.options.no_gdiplus) {
+        return run_with(&GdiBackend, request);
+    }
+    run(&Session::new()?, request)
+}
+
+// capture a request through `session`; see Session::capture_request.
+pub(crate) fn run(session: &Session, request: &CaptureRequest) -> Result<CaptureResult> {
+    run_with(session.backend.as_ref(), request)
+}
+
+fn run_with(backend: &dyn CaptureBackend, request: &CaptureRequest) -> Result<CaptureResult> {
+    let request = &request.0;
+    let rect = resolve(request)?;
+    let options = &request.options;
+    if let Some(file) = &request.file {
+        let ((), all_black) = capture_with(backend, rect, options, request.check_black, |hbmp| {
+            save_hbitmap(hbmp, file, options)
+        })?;
+        return Ok(CaptureResult {
+            rect,
+            file: Some(file.clone()),
+            image: None,
+            all_black,
+        });
+    }
+    let format = options.format.as_deref().unwrap_or("png");
+    let (image, all_black) = capture_with(backend, rect, options, request.check_black, |hbmp| {
+        encode_hbitmap(hbmp, format, options)
+    })?;
+    Ok(CaptureResult {
+        rect,
+        file: None,
+        image: Some(image),
+        all_black,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_checks_settings() {
+        let request = CaptureOptions::new()
+            .cursor(true)
+            .quality(85)
+            .scale(50)
+            .build()
+            .unwrap();
+        let options = request.options();
+        assert!(options.include_cursor);
+        assert_eq!((options.quality, options.scale), (Some(85), Some(50)));
+        assert!(matches!(
+            CaptureOptions::new().quality(101).build(),
+            Err(SnapshotError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            CaptureOptions::new().png_compression(10).build(),
+            Err(SnapshotError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            CaptureOptions::new().dip(true).build(),
+            Err(SnapshotError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            CaptureOptions::new().rect(0, 0, 0, 10).build(),
+            Err(SnapshotError::InvalidRegion(_))
+        ));
+    }
+
+    #[test]
+    fn setters_refine_the_starting_options() {
+        let base = Options {
+            verbose: true,
+            quality: Some(40),
+            ..Default::default()
+        };
+        let request = CaptureOptions::from_options(base)
+            .quality(90)
+            .build()
+            .unwrap();
+        assert!(request.options().verbose);
+        assert_eq!(request.options().quality, Some(90));
+    }
+}
@@ -0,0 +1,137 @@
+// Capture-and-compare against a reference image for --compare.
+
+use std::ptr::null_mut;
+
+use windows::Win32::Foundation::E_FAIL;
+use windows::Win32::Graphics::Gdi;
+use windows::Win32::Graphics::GdiPlus;
+use windows::core::{Error, HRESULT, PCWSTR};
+
+use crate::backend::CaptureBackend;
+use crate::{
+    ImgGuard, Options, PIXEL_FORMAT_32BPP_ARGB, Result, SnapshotError, encoder_for_filename,
+    gdip_check, pixels, prepare_image, save_image, wide_path, write_atomically,
+};
+
+/// Result of [`crate::Session::compare`].
+pub struct CompareReport {
+    pub width: i32,
+    pub height: i32,
+    /// Pixels whose RGB value differs from the reference (alpha is ignored).
+    pub differing: u64,
+}
+
+impl CompareReport {
+    /// Share of differing pixels, 0.0 to 100.0.
+    pub fn percent(&self) -> f64 {
+        let total = self.width as u64 * self.height as u64;
+        if total == 0 {
+            0.0
+        } else {
+            self.differing as f64 * 100.0 / total as f64
+        }
+    }
+}
+
+// top-down, tightly packed BGRA copy of an image.
+fn packed_bgra(img: &ImgGuard) -> Result<(i32, i32, Vec<u8>)> {
+    pixels::lock_bits(img, false, |buf, w, h, stride| {
+        let row = w as usize * 4;
+        let mut out = Vec::with_capacity(row * h as usize);
+        for y in 0..h as usize {
+            out.extend_from_slice(&buf[y * stride..y * stride + row]);
+        }
+        (w, h, out)
+    })
+}
+
+// per-pixel "RGB differs" mask of two equally sized BGRA buffers.
+fn diff_mask(a: &[u8], b: &[u8]) -> Vec<bool> {
+    a.chunks_exact(4)
+        .zip(b.chunks_exact(4))
+        .map(|(p, q)| p[..3] != q[..3])
+        .collect()
+}
+
+// changed pixels in opaque red over a dimmed grey copy of the capture.
+fn write_diff(
+    filename: &str,
+    w: i32,
+    h: i32,
+    capture: &[u8],
+    mask: &[bool],
+    options: &Options,
+) -> Result<()> {
+    let mut bmp = null_mut();
+    unsafe {
+        gdip_check(
+            GdiPlus::GdipCreateBitmapFromScan0(w, h, 0, PIXEL_FORMAT_32BPP_ARGB, None, &mut bmp),
+            "GdipCreateBitmapFromScan0",
+        )?;
+    }
+    let img = ImgGuard(bmp as *mut GdiPlus::GpImage);
+    pixels::lock_bits(&img, true, |dst, w, h, stride| {
+        for y in 0..h as usize {
+            let row = &mut dst[y * stride..y * stride + w as usize * 4];
+            for (x, px) in row.chunks_exact_mut(4).enumerate() {
+                let i = y * w as usize + x;
+                if mask[i] {
+                    px.copy_from_slice(&[0, 0, 255, 255]); // BGRA red
+                } else {
+                    let s = &capture[i * 4..i * 4 + 3];
+                    let grey = ((s[0] as u32 + s[1] as u32 + s[2] as u32) / 9) as u8;
+                    px.copy_from_slice(&[grey, grey, grey, 255]);
+                }
+            }
+        }
+    })?;
+    let clsid = encoder_for_filename(filename)?;
+    write_atomically(filename, !options.no_atomic, |path| {
+        save_image(&img, path, &clsid, None)
+    })
+}
+
+pub(crate) fn run(
+    backend: &dyn CaptureBackend,
+    rect: (i32, i32, i32, i32),
+    reference: &str,
+    diff_out: Option<&str>,
+    options: &Options,
+) -> Result<CompareReport> {
+    let mut raw = null_mut();
+    let wname = wide_path(reference);
+    unsafe {
+        gdip_check(
+            GdiPlus::GdipLoadImageFromFile(PCWSTR(wname.as_ptr()), &mut raw),
+            "GdipLoadImageFromFile",
+        )?;
+    }
+    let reference_img = ImgGuard(raw);
+
+    let hbmp = backend.capture(rect, options)?;
+    let prepared = prepare_image(hbmp, options, false);
+    unsafe {
+        let _ = Gdi::DeleteObject(hbmp.into());
+    }
+    let captured = prepared?;
+
+    let (rw, rh, ref_px) = packed_bgra(&reference_img)?;
+    let (cw, ch, cap_px) = packed_bgra(&captured)?;
+    if (rw, rh) != (cw, ch) {
+        return Err(SnapshotError::InvalidRegion(format!(
+            "capture is {cw}x{ch} but the reference is {rw}x{rh}"
+        )));
+    }
+    let mask = diff_mask(&cap_px, &ref_px);
+    if mask.len() != (cw * ch) as usize {
+        return Err(Error::new(HRESULT(E_FAIL.0), "pixel buffers differ in size").into());
+    }
+    if let Some(file) = diff_out {
+        write_diff(file, cw, ch, &cap_px, &mask, options)?;
+    }
+    Ok(CompareReport {
+        width: cw,
+        height: ch,
+        differing: mask.iter().filter(|&&d| d).count() as u64,
+    })
+}
@@ -0,0 +1,117 @@
+// --dwm-thumbnail: have DWM render a live thumbnail of a window into a topmost helper window
+// laid exactly over it, then BitBlt that part of the screen as usual. DWM composes the
+// thumbnail from the window's redirection surface, so this shows the window even where it is
+// covered by other windows, and gives correct content for some GPU-rendered windows that come
+// back black through a plain screen copy.
+
+use windows::Win32::Foundation::{COLORREF, E_FAIL, HWND, POINT, RECT};
+use windows::Win32::Graphics::Dwm::{
+    DWM_THUMBNAIL_PROPERTIES, DWM_TNP_OPACITY, DWM_TNP_RECTDESTINATION, DWM_TNP_RECTSOURCE,
+    DWM_TNP_SOURCECLIENTAREAONLY, DWM_TNP_VISIBLE, DwmFlush, DwmRegisterThumbnail,
+    DwmUnregisterThumbnail, DwmUpdateThumbnailProperties,
+};
+use windows::Win32::Graphics::Gdi::{self, ClientToScreen};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DestroyWindow, GetWindowRect, LWA_ALPHA, SW_SHOWNOACTIVATE,
+    SetLayeredWindowAttributes, ShowWindow, WS_EX_LAYERED, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW,
+    WS_EX_TOPMOST, WS_EX_TRANSPARENT, WS_POPUP,
+};
+use windows::core::{Error, HRESULT, w};
+
+use crate::{Options, Result, capture_region};
+
+struct HelperWindow(HWND);
+impl Drop for HelperWindow {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = DestroyWindow(self.0);
+        }
+    }
+}
+
+struct ThumbnailGuard(isize);
+impl Drop for ThumbnailGuard {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = DwmUnregisterThumbnail(self.0);
+        }
+    }
+}
+
+/// Capture the screen rectangle (x, y, w, h) of `source` from its DWM thumbnail into a new
+/// DIB section. The rectangle must lie within the window (or, with `client_only`, within its
+/// client area); protected-content windows still come back black.
+pub(crate) fn capture_thumbnail(
+    source: HWND,
+    client_only: bool,
+    (x, y, w, h): (i32, i32, i32, i32),
+    options: &Options,
+) -> Result<Gdi::HBITMAP> {
+    // thumbnail source coordinates are relative to the window's (or client area's) top-left,
+    // where the window origin includes the invisible resize borders
+    let origin = if client_only {
+        let mut p = POINT::default();
+        if !unsafe { ClientToScreen(source, &mut p) }.as_bool() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "ClientToScreen failed").into());
+        }
+        p
+    } else {
+        let mut r = RECT::default();
+        unsafe { GetWindowRect(source, &mut r)? };
+        POINT {
+            x: r.left,
+            y: r.top,
+        }
+    };
+    // click-through, never activated, not on the taskbar
+    let helper = HelperWindow(unsafe {
+        CreateWindowExW(
+            WS_EX_LAYERED | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE | WS_EX_TRANSPARENT,
+            w!("STATIC"),
+            w!(""),
+            WS_POPUP,
+            x,
+            y,
+            w,
+            h,
+            None,
+            None,
+            Some(GetModuleHandleW(None)?.into()),
+            None,
+        )?
+    });
+    unsafe { SetLayeredWindowAttributes(helper.0, COLORREF(0), 255, LWA_ALPHA)? };
+    let thumb = ThumbnailGuard(unsafe { DwmRegisterThumbnail(helper.0, source)? });
+    let (sx, sy) = (x - origin.x, y - origin.y);
+    let props = DWM_THUMBNAIL_PROPERTIES {
+        dwFlags: DWM_TNP_RECTDESTINATION
+            | DWM_TNP_RECTSOURCE
+            | DWM_TNP_OPACITY
+            | DWM_TNP_VISIBLE
+            | DWM_TNP_SOURCECLIENTAREAONLY,
+        rcDestination: RECT {
+            left: 0,
+            top: 0,
+            right: w,
+            bottom: h,
+        },
+        rcSource: RECT {
+            left: sx,
+            top: sy,
+            right: sx + w,
+            bottom: sy + h,
+        },
+        opacity: 255,
+        fVisible: true.into(),
+        fSourceClientAreaOnly: client_only.into(),
+    };
+    unsafe {
+        DwmUpdateThumbnailProperties(thumb.0, &props)?;
+        let _ = ShowWindow(helper.0, SW_SHOWNOACTIVATE);
+        // one composition pass to show the helper, one more for the thumbnail inside it
+        DwmFlush()?;
+        DwmFlush()?;
+    }
+    capture_region(x, y, w, h, options)
+}
@@ -0,0 +1,74 @@
+// Encoded images held in memory, and their base64 / data: URI text forms.
+
+/// An image encoded in memory by a GDI+ encoder, ready to embed or send.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodedImage {
+    pub bytes: Vec<u8>,
+    /// The encoder's MIME type, e.g. "image/png".
+    pub mime_type: String,
+}
+
+impl EncodedImage {
+    /// The bytes as standard (RFC 4648) base64 with `=` padding and no line breaks.
+    pub fn base64(&self) -> String {
+        base64(&self.bytes)
+    }
+
+    /// A `data:<mime>;base64,...` URI for HTML `img` tags or JSON payloads.
+    pub fn data_uri(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.base64())
+    }
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// 3 bytes -> 4 characters; a short last group is padded with '='. small enough that a
+// dependency isn't worth it.
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | ((b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_matches_rfc_4648_vectors() {
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, want) in cases {
+            assert_eq!(base64(input.as_bytes()), want, "{input:?}");
+        }
+        assert_eq!(base64(&[0xff, 0xfe, 0xfd]), "//79");
+    }
+
+    #[test]
+    fn data_uri_carries_the_mime_type() {
+        let img = EncodedImage {
+            bytes: b"foo".to_vec(),
+            mime_type: "image/png".into(),
+        };
+        assert_eq!(img.data_uri(), "data:image/png;base64,Zm9v");
+    }
+}
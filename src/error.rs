@@ -0,0 +1,78 @@
+// Error type for the capture/save pipeline.
+
+use std::fmt;
+
+/// Errors from capturing and saving.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The requested rectangle is empty, off the desktop or doesn't fit its window.
+    InvalidRegion(String),
+    /// No installed encoder handles this file extension.
+    NoEncoder(String),
+    /// GDI+ reports no image encoders at all (seen in stripped-down Windows containers).
+    NoEncodersInstalled,
+    /// A setting is out of range, or doesn't apply to the chosen target.
+    InvalidOption(String),
+    /// Gave up waiting, e.g. for a window to appear.
+    Timeout(String),
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// Any other Win32 / GDI+ failure.
+    Windows(windows::core::Error),
+}
+
+pub type Result<T, E = SnapshotError> = std::result::Result<T, E>;
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::InvalidRegion(msg)
+            | SnapshotError::InvalidOption(msg)
+            | SnapshotError::Timeout(msg) => f.write_str(msg),
+            SnapshotError::NoEncoder(ext) if ext.is_empty() => {
+                f.write_str("no output format: the filename has no extension")
+            }
+            // optional codecs that many systems lack: point at a format that always works
+            SnapshotError::NoEncoder(ext)
+                if matches!(
+                    ext.to_ascii_lowercase().as_str(),
+                    "webp" | "heic" | "heif" | "avif"
+                ) =>
+            {
+                write!(
+                    f,
+                    "no GDI+ encoder for .{ext} files: the codec isn't installed on this \
+                     system; use .png, or .jpg with --quality for smaller files"
+                )
+            }
+            SnapshotError::NoEncoder(ext) => write!(f, "no GDI+ encoder for .{ext} files"),
+            SnapshotError::NoEncodersInstalled => {
+                f.write_str("no GDI+ image encoders are installed")
+            }
+            SnapshotError::Io(e) => e.fmt(f),
+            SnapshotError::Windows(e) => f.write_str(&e.message()),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(e) => Some(e),
+            SnapshotError::Windows(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<windows::core::Error> for SnapshotError {
+    fn from(e: windows::core::Error) -> Self {
+        SnapshotError::Windows(e)
+    }
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
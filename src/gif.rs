@@ -0,0 +1,198 @@
+// Animated GIF output: frames are appended to one file with GdipSaveAddImage.
+
+use std::ffi::c_void;
+use std::time::Duration;
+
+use windows::Win32::Graphics::Gdi;
+use windows::Win32::Graphics::GdiPlus;
+use windows::core::{GUID, PCWSTR};
+
+use crate::{ImgGuard, Options, Result, Session, clsid_for_extension, gdip_check};
+use crate::{prepare_image, save_check, wide_path};
+
+// one EncoderSaveFlag parameter; `value` must outlive the GDI+ call using the result.
+fn save_flag(value: &mut u32) -> GdiPlus::EncoderParameters {
+    GdiPlus::EncoderParameters {
+        Count: 1,
+        Parameter: [GdiPlus::EncoderParameter {
+            Guid: GdiPlus::EncoderSaveFlag,
+            NumberOfValues: 1,
+            Type: GdiPlus::EncoderParameterValueTypeLong.0 as u32,
+            Value: value as *mut u32 as *mut c_void,
+        }],
+    }
+}
+
+/// Writes frames into a single animated GIF that loops forever. The first frame carries the
+/// per-frame delay table, so the frame count must be known up front.
+pub struct GifWriter<'a> {
+    session: &'a Session,
+    filename: String,
+    clsid: GUID,
+    delay_cs: u32, // GIF delays are in 1/100 s
+    frames: u32,
+    first: Option<ImgGuard>,
+}
+
+impl<'a> GifWriter<'a> {
+    pub(crate) fn new(
+        session: &'a Session,
+        filename: &str,
+        delay: Duration,
+        frames: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            session,
+            filename: filename.to_string(),
+            clsid: clsid_for_extension("gif")?,
+            delay_cs: (delay.as_millis() / 10).min(u32::MAX as u128) as u32,
+            frames,
+            first: None,
+        })
+    }
+
+    /// Capture the screen rectangle (x, y, w, h), apply `options` (e.g. `scale`), and append it.
+    pub fn add_frame(&mut self, x: i32, y: i32, w: i32, h: i32, options: &Options) -> Result<()> {
+        let hbmp = self.session.backend.capture((x, y, w, h), options)?;
+        let img = prepare_image(hbmp, options, false);
+        unsafe {
+            let _ = Gdi::DeleteObject(hbmp.into());
+        }
+        let img = img?;
+        match &self.first {
+            None => {
+                self.set_timing(&img)?;
+                let mut flag = GdiPlus::EncoderValueMultiFrame.0 as u32;
+                let params = save_flag(&mut flag);
+                let wname = wide_path(&self.filename);
+                unsafe {
+                    save_check(
+                        GdiPlus::GdipSaveImageToFile(
+                            img.0,
+                            PCWSTR(wname.as_ptr()),
+                            &self.clsid,
+                            &params,
+                        ),
+                        "GdipSaveImageToFile",
+                        &self.filename,
+                    )?;
+                }
+                self.first = Some(img);
+            }
+            Some(first) => {
+                let mut flag = GdiPlus::EncoderValueFrameDimensionTime.0 as u32;
+                let params = save_flag(&mut flag);
+                unsafe {
+                    save_check(
+                        GdiPlus::GdipSaveAddImage(first.0, img.0, &params),
+                        "GdipSaveAddImage",
+                        &self.filename,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // frame delay table (one entry per frame) and loop count (0 = forever) on the first frame.
+    fn set_timing(&self, img: &ImgGuard) -> Result<()> {
+        let mut delays = vec![self.delay_cs; self.frames.max(1) as usize];
+        let mut loops = 0u16;
+        let delay_item = GdiPlus::PropertyItem {
+            id: GdiPlus::PropertyTagFrameDelay,
+            length: (delays.len() * 4) as u32,
+            r#type: GdiPlus::PropertyTagTypeLong as u16,
+            value: delays.as_mut_ptr() as *mut c_void,
+        };
+        let loop_item = GdiPlus::PropertyItem {
+            id: GdiPlus::PropertyTagLoopCount,
+            length: 2,
+            r#type: GdiPlus::PropertyTagTypeShort as u16,
+            value: &mut loops as *mut u16 as *mut c_void,
+        };
+        unsafe {
+            gdip_check(
+                GdiPlus::GdipSetPropertyItem(img.0, &delay_item),
+                "GdipSetPropertyItem",
+            )?;
+            gdip_check(
+                GdiPlus::GdipSetPropertyItem(img.0, &loop_item),
+                "GdipSetPropertyItem",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Flush and close the file. A writer dropped without `finish` leaves an incomplete GIF.
+    pub fn finish(self) -> Result<()> {
+        let Some(first) = &self.first else {
+            return Ok(()); // nothing was written
+        };
+        let mut flag = GdiPlus::EncoderValueFlush.0 as u32;
+        let params = save_flag(&mut flag);
+        let status = unsafe { GdiPlus::GdipSaveAdd(first.0, &params) };
+        save_check(status, "GdipSaveAdd", &self.filename)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake_session;
+
+    // the raw value of property `id` on a loaded image
+    fn property(img: &ImgGuard, id: u32) -> Vec<u8> {
+        let mut size = 0;
+        unsafe {
+            assert_eq!(
+                GdiPlus::GdipGetPropertyItemSize(img.0, id, &mut size),
+                GdiPlus::Ok
+            );
+            // u64s keep the PropertyItem header aligned
+            let mut buf = vec![0u64; (size as usize).div_ceil(8)];
+            let item = buf.as_mut_ptr() as *mut GdiPlus::PropertyItem;
+            assert_eq!(
+                GdiPlus::GdipGetPropertyItem(img.0, id, size, item),
+                GdiPlus::Ok
+            );
+            let item = &*item;
+            std::slice::from_raw_parts(item.value as *const u8, item.length as usize).to_vec()
+        }
+    }
+
+    #[test]
+    fn gif_loops_forever_with_the_frame_delay() {
+        let session = fake_session();
+        let path = std::env::temp_dir().join(format!(
+            "gdip_snapshot_test_{}_frames.gif",
+            std::process::id()
+        ));
+        let file = path.to_str().unwrap();
+        let mut gif = session.gif(file, Duration::from_millis(250), 3).unwrap();
+        for _ in 0..3 {
+            gif.add_frame(0, 0, 32, 24, &Options::default()).unwrap();
+        }
+        gif.finish().unwrap();
+
+        let wname = wide_path(file);
+        let mut bitmap = std::ptr::null_mut();
+        let status =
+            unsafe { GdiPlus::GdipCreateBitmapFromFile(PCWSTR(wname.as_ptr()), &mut bitmap) };
+        assert_eq!(status, GdiPlus::Ok);
+        let img = ImgGuard(bitmap as *mut GdiPlus::GpImage);
+        let mut frames = 0;
+        let status = unsafe {
+            GdiPlus::GdipImageGetFrameCount(img.0, &GdiPlus::FrameDimensionTime, &mut frames)
+        };
+        assert_eq!((status, frames), (GdiPlus::Ok, 3));
+        assert_eq!(property(&img, GdiPlus::PropertyTagLoopCount), [0, 0]);
+        let delays = property(&img, GdiPlus::PropertyTagFrameDelay);
+        let delays: Vec<u32> = delays
+            .chunks_exact(4)
+            .map(|d| u32::from_le_bytes(d.try_into().unwrap()))
+            .collect();
+        assert_eq!(delays, [25, 25, 25]);
+        drop(img);
+        let _ = std::fs::remove_file(path);
+    }
+}
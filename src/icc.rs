@@ -0,0 +1,117 @@
+// ICC profile embedding (PropertyTagICCProfile) and a bundled minimal sRGB profile.
+
+use std::ffi::c_void;
+
+use windows::Win32::Graphics::GdiPlus;
+
+use crate::{ImgGuard, Result, gdip_check};
+
+// formats whose GDI+ encoders write a profile property (PNG iCCP, JPEG APP2, TIFF tag).
+pub(crate) fn carries_icc(ext: &str) -> bool {
+    matches!(
+        ext.to_ascii_lowercase().as_str(),
+        "png" | "jpg" | "jpeg" | "jpe" | "jfif" | "tif" | "tiff"
+    )
+}
+
+pub(crate) fn attach_icc(img: &ImgGuard, profile: &[u8]) -> Result<()> {
+    let item = GdiPlus::PropertyItem {
+        id: GdiPlus::PropertyTagICCProfile,
+        length: profile.len() as u32,
+        r#type: GdiPlus::PropertyTagTypeUndefined as u16,
+        value: profile.as_ptr() as *mut c_void, // GDI+ copies the value
+    };
+    unsafe {
+        gdip_check(
+            GdiPlus::GdipSetPropertyItem(img.0, &item),
+            "GdipSetPropertyItem",
+        )
+    }
+}
+
+fn s15f16(v: f64) -> [u8; 4] {
+    ((v * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(x: f64, y: f64, z: f64) -> Vec<u8> {
+    let mut t = b"XYZ \0\0\0\0".to_vec();
+    for v in [x, y, z] {
+        t.extend(s15f16(v));
+    }
+    t
+}
+
+/// A minimal ICC v2 display profile for sRGB: D50-adapted primaries, D50 white point and the
+/// sRGB transfer curve sampled into a 1024-entry table.
+pub fn srgb_icc_profile() -> Vec<u8> {
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    let name = b"sRGB\0";
+    desc.extend((name.len() as u32).to_be_bytes());
+    desc.extend(name);
+    desc.extend([0u8; 4 + 4 + 2 + 1 + 67]); // empty Unicode and ScriptCode descriptions
+
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    let n = 1024u32;
+    curve.extend(n.to_be_bytes());
+    for i in 0..n {
+        let c = i as f64 / (n - 1) as f64;
+        let linear = if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        };
+        curve.extend(((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", desc),
+        (b"cprt", b"text\0\0\0\0No copyright, use freely\0".to_vec()),
+        (b"wtpt", xyz_tag(0.9642, 1.0, 0.8249)),
+        (b"rXYZ", xyz_tag(0.4361, 0.2225, 0.0139)),
+        (b"gXYZ", xyz_tag(0.3851, 0.7169, 0.0971)),
+        (b"bXYZ", xyz_tag(0.1431, 0.0606, 0.7141)),
+        (b"rTRC", curve),
+    ];
+
+    // tag table: the three TRC tags share one curve
+    let entries = tags.len() + 2;
+    let mut offset = 128 + 4 + entries * 12;
+    let mut table = (entries as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let mut trc = (0, 0);
+    for (sig, body) in &tags {
+        table.extend(*sig);
+        table.extend((offset as u32).to_be_bytes());
+        table.extend((body.len() as u32).to_be_bytes());
+        if *sig == b"rTRC" {
+            trc = (offset, body.len());
+        }
+        data.extend(body);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        offset = 128 + 4 + entries * 12 + data.len();
+    }
+    for sig in [b"gTRC", b"bTRC"] {
+        table.extend(sig);
+        table.extend((trc.0 as u32).to_be_bytes());
+        table.extend((trc.1 as u32).to_be_bytes());
+    }
+
+    let size = 128 + table.len() + data.len();
+    let mut p = Vec::with_capacity(size);
+    p.extend((size as u32).to_be_bytes());
+    p.extend([0u8; 4]); // preferred CMM
+    p.extend([2, 0x10, 0, 0]); // version 2.1
+    p.extend(b"mntrRGB XYZ ");
+    p.extend([0u8; 12]); // creation date
+    p.extend(b"acsp");
+    p.extend([0u8; 4 + 4 + 4 + 4 + 8 + 4]); // platform, flags, device, attributes, intent
+    for v in [0.9642, 1.0, 0.8249] {
+        p.extend(s15f16(v)); // PCS illuminant (D50)
+    }
+    p.extend([0u8; 4 + 16 + 28]); // creator, profile id, reserved
+    p.extend(table);
+    p.extend(data);
+    p
+}
@@ -0,0 +1,94 @@
+// Owned, handle-free capture results.
+
+use windows::Win32::Graphics::Gdi;
+
+use crate::{Options, Result, SnapshotError, capture_region, pixels};
+
+/// A captured image with owned pixels: `height` rows of `width * 4` bytes, top row first,
+/// each pixel in B, G, R, A order with A = 255.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageBuffer {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<u8>,
+}
+
+impl ImageBuffer {
+    /// The four BGRA bytes at (x, y), or `None` outside the image.
+    pub fn pixel(&self, x: i32, y: i32) -> Option<[u8; 4]> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[i..i + 4].try_into().ok()
+    }
+}
+
+/// Capture the screen rectangle `region` (x, y, w, h) into an [`ImageBuffer`]. No GDI or
+/// GDI+ handles outlive the call and GDI+ is not needed, so this is the simplest entry point
+/// for processing screenshots in Rust. A region with a zero or negative size is an
+/// [`SnapshotError::InvalidRegion`].
+pub fn capture_image(region: (i32, i32, i32, i32)) -> Result<ImageBuffer> {
+    let (x, y, w, h) = region;
+    if w <= 0 || h <= 0 {
+        return Err(SnapshotError::InvalidRegion(format!(
+            "rectangle {w}x{h} is empty"
+        )));
+    }
+    let hbmp = capture_region(x, y, w, h, &Options::default())?;
+    let image = image_from_hbitmap(hbmp);
+    unsafe {
+        let _ = Gdi::DeleteObject(hbmp.into());
+    }
+    image
+}
+
+// copy a 32bpp bottom-up DIB section into top-down BGRA with opaque alpha (BitBlt leaves the
+// fourth byte undefined).
+pub(crate) fn image_from_hbitmap(hbmp: Gdi::HBITMAP) -> Result<ImageBuffer> {
+    let mut bits = pixels::dib_bits(hbmp)?;
+    let (w, h, stride) = (bits.width, bits.height, bits.stride);
+    let src = bits.bytes_mut();
+    let row = w as usize * 4;
+    let mut out = Vec::with_capacity(row * h as usize);
+    for y in (0..h as usize).rev() {
+        out.extend_from_slice(&src[y * stride..y * stride + row]);
+    }
+    for px in out.chunks_exact_mut(4) {
+        px[3] = 255;
+    }
+    Ok(ImageBuffer {
+        width: w,
+        height: h,
+        pixels: out,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake_image;
+
+    #[test]
+    fn image_buffer_is_top_down_opaque_bgra() {
+        let bmp = fake_image(8, 4, &Options::default());
+        let image = image_from_hbitmap(bmp.0).unwrap();
+        assert_eq!(
+            (image.width, image.height, image.pixels.len()),
+            (8, 4, 8 * 4 * 4)
+        );
+        assert_eq!(image.pixel(0, 0), Some([0, 0, 128, 255]));
+        assert_eq!(image.pixel(7, 3), Some([255, 255, 128, 255]));
+        assert_eq!(image.pixel(8, 0), None);
+    }
+
+    #[test]
+    fn empty_or_negative_regions_are_rejected() {
+        for region in [(0, 0, 0, 4), (0, 0, 4, 0), (0, 0, 4, -4), (0, 0, -4, 4)] {
+            assert!(
+                matches!(capture_image(region), Err(SnapshotError::InvalidRegion(_))),
+                "{region:?}"
+            );
+        }
+    }
+}
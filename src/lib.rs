@@ -0,0 +1,2104 @@
+//! GDI/GDI+ screen capture: BitBlt a screen rectangle into a DIB section and save it
+//! through the GDI+ image encoders.
+
+mod backend;
+mod bench;
+mod capture;
+mod compare;
+mod dwm;
+mod encoded;
+mod error;
+mod gif;
+mod icc;
+mod image;
+mod monitors;
+mod pipe;
+mod pixels;
+mod selftest;
+mod stream;
+mod window;
+
+pub use bench::{BenchReport, PhaseStats};
+pub use capture::{CaptureOptions, CaptureRequest, CaptureResult, CaptureTarget, capture};
+pub use compare::CompareReport;
+pub use encoded::EncodedImage;
+pub use error::{Result, SnapshotError};
+pub use gif::GifWriter;
+pub use icc::srgb_icc_profile;
+pub use image::{ImageBuffer, capture_image};
+pub use monitors::{capture_monitors, monitor_by_name, monitor_names, monitor_rects};
+pub use pipe::is_pipe_path;
+pub use selftest::FormatCheck;
+pub use stream::capture_stream;
+pub use window::{
+    WindowSelector, find_window, find_window_by_title, find_windows_by_title, pad_rect, parse_hwnd,
+    sub_rect, wait_for_window, window_rect,
+};
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, c_void};
+use std::iter::once;
+use std::mem::{size_of, zeroed};
+use std::os::windows::ffi::OsStrExt;
+use std::ptr::{null, null_mut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use windows::Win32::Foundation::{E_FAIL, HGLOBAL, HWND, POINT, RECT};
+use windows::Win32::Graphics::Gdi;
+use windows::Win32::Graphics::Gdi::{CAPTUREBLT, ROP_CODE, SRCCOPY};
+use windows::Win32::Graphics::GdiPlus;
+use windows::Win32::System::Com::StructuredStorage::{CreateStreamOnHGlobal, GetHGlobalFromStream};
+use windows::Win32::System::Com::{CoTaskMemAlloc, CoTaskMemFree, STREAM_SEEK_CUR};
+use windows::Win32::System::Memory::{GlobalLock, GlobalUnlock};
+use windows::Win32::UI::Shell::GetScaleFactorForMonitor;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CURSOR_SHOWING, CURSORINFO, DI_NORMAL, DrawIconEx, GetCursorInfo, GetCursorPos, GetIconInfo,
+    GetSystemMetrics, HICON, ICONINFO, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_REMOTESESSION,
+    SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN,
+};
+use windows::core::{Error, GUID, HRESULT, PCWSTR};
+
+fn wide<S: AsRef<OsStr>>(s: S) -> Vec<u16> {
+    s.as_ref().encode_wide().chain(once(0)).collect()
+}
+
+// paths this long (in UTF-16 units, like MAX_PATH) need the \\?\ prefix for GDI+.
+const MAX_PATH: usize = 260;
+
+// NUL-terminated UTF-16 of a file path for GDI+ file APIs, which are limited to MAX_PATH
+// unless given an extended-length path. Long paths are made absolute and prefixed.
+fn wide_path(filename: &str) -> Vec<u16> {
+    if OsStr::new(filename).encode_wide().count() < MAX_PATH || pipe::is_pipe_path(filename) {
+        return wide(filename);
+    }
+    match std::path::absolute(filename) {
+        Ok(abs) => wide(extended_length_path(&abs.to_string_lossy())),
+        Err(_) => wide(filename), // let the API report the problem
+    }
+}
+
+// "C:\a\b" -> "\\?\C:\a\b", "\\server\share\x" -> "\\?\UNC\server\share\x". The extended form
+// skips Win32 normalization, so the path must already be absolute with backslashes.
+fn extended_length_path(abs: &str) -> String {
+    if abs.starts_with(r"\\?\") || abs.starts_with(r"\\.\") {
+        abs.to_string()
+    } else if let Some(unc) = abs.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{unc}")
+    } else {
+        format!(r"\\?\{abs}")
+    }
+}
+
+struct EncodersGuard(*mut c_void);
+impl Drop for EncodersGuard {
+    fn drop(&mut self) {
+        unsafe { CoTaskMemFree(Some(self.0)) }
+    }
+}
+
+struct ScreenDcGuard(Gdi::HDC);
+impl Drop for ScreenDcGuard {
+    fn drop(&mut self) {
+        unsafe {
+            Gdi::ReleaseDC(None, self.0);
+        }
+    }
+}
+
+struct DcGuard(Gdi::HDC);
+impl Drop for DcGuard {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = Gdi::DeleteDC(self.0);
+        }
+    }
+}
+
+struct BitmapGuard(Gdi::HBITMAP);
+impl Drop for BitmapGuard {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = Gdi::DeleteObject(self.0.into());
+        }
+    }
+}
+
+struct SelectGuard {
+    dc: Gdi::HDC,
+    old: Gdi::HGDIOBJ,
+}
+impl Drop for SelectGuard {
+    fn drop(&mut self) {
+        unsafe {
+            Gdi::SelectObject(self.dc, self.old);
+        }
+    }
+}
+
+struct GdiplusGuard(usize);
+impl GdiplusGuard {
+    fn new() -> Result<Self> {
+        gdip_startup().map(Self)
+    }
+}
+impl Drop for GdiplusGuard {
+    fn drop(&mut self) {
+        gdip_shutdown(self.0);
+    }
+}
+
+struct ImgGuard(*mut GdiPlus::GpImage);
+impl Drop for ImgGuard {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { GdiPlus::GdipDisposeImage(self.0) };
+        }
+    }
+}
+
+struct GraphicsGuard(*mut GdiPlus::GpGraphics);
+impl Drop for GraphicsGuard {
+    fn drop(&mut self) {
+        unsafe { GdiPlus::GdipDeleteGraphics(self.0) };
+    }
+}
+
+struct AttrGuard(*mut GdiPlus::GpImageAttributes);
+impl Drop for AttrGuard {
+    fn drop(&mut self) {
+        unsafe { GdiPlus::GdipDisposeImageAttributes(self.0) };
+    }
+}
+
+struct FontFamilyGuard(*mut GdiPlus::GpFontFamily);
+impl Drop for FontFamilyGuard {
+    fn drop(&mut self) {
+        unsafe { GdiPlus::GdipDeleteFontFamily(self.0) };
+    }
+}
+
+struct FontGuard(*mut GdiPlus::GpFont);
+impl Drop for FontGuard {
+    fn drop(&mut self) {
+        unsafe { GdiPlus::GdipDeleteFont(self.0) };
+    }
+}
+
+struct BrushGuard(*mut GdiPlus::GpBrush);
+impl Drop for BrushGuard {
+    fn drop(&mut self) {
+        unsafe { GdiPlus::GdipDeleteBrush(self.0) };
+    }
+}
+
+struct StringFormatGuard(*mut GdiPlus::GpStringFormat);
+impl Drop for StringFormatGuard {
+    fn drop(&mut self) {
+        unsafe { GdiPlus::GdipDeleteStringFormat(self.0) };
+    }
+}
+
+// GDI+ pixel formats (Gdipluspixelformats.h); not exported by the windows crate.
+const PIXEL_FORMAT_32BPP_RGB: i32 = 0x0002_2009;
+const PIXEL_FORMAT_32BPP_ARGB: i32 = 0x0026_200A;
+const PIXEL_FORMAT_8BPP_INDEXED: i32 = 0x0003_0803;
+
+fn gdip_check(status: GdiPlus::Status, what: &str) -> Result<()> {
+    if status != GdiPlus::Ok {
+        return Err(Error::new(HRESULT(E_FAIL.0), format!("{what} failed")).into());
+    }
+    Ok(())
+}
+
+// like gdip_check, for the GDI+ calls that write an image file: a missing directory, a
+// read-only share or a full disk fail there, so the failure is an I/O error.
+fn save_check(status: GdiPlus::Status, what: &str, filename: &str) -> Result<()> {
+    let kind = match status {
+        GdiPlus::Ok => return Ok(()),
+        GdiPlus::FileNotFound => std::io::ErrorKind::NotFound,
+        GdiPlus::AccessDenied => std::io::ErrorKind::PermissionDenied,
+        GdiPlus::Win32Error => {
+            let os = std::io::Error::last_os_error();
+            let msg = format!("{what} failed for {filename}: {os}");
+            return Err(SnapshotError::Io(std::io::Error::new(os.kind(), msg)));
+        }
+        _ => std::io::ErrorKind::Other,
+    };
+    let msg = format!("{what} failed for {filename} (GDI+ status {})", status.0);
+    Err(SnapshotError::Io(std::io::Error::new(kind, msg)))
+}
+
+/// Corner a `label` is drawn in.
+#[derive(Clone, Copy, Default)]
+pub enum LabelPos {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+/// Bits per pixel of the capture DIB section.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitDepth {
+    /// 24bpp BGR; smaller files for formats without alpha (mainly BMP).
+    Bits24,
+    #[default]
+    Bits32,
+}
+
+impl BitDepth {
+    pub fn bits(self) -> u16 {
+        match self {
+            BitDepth::Bits24 => 24,
+            BitDepth::Bits32 => 32,
+        }
+    }
+}
+
+/// Drop shadow composited behind a capture on a transparent, enlarged canvas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shadow {
+    /// Blur radius in pixels; the canvas also gets this much margin on every side.
+    pub blur: u32,
+    /// Shadow displacement (right, down) from the image, in pixels.
+    pub offset: (i32, i32),
+    /// Red, green, blue and opacity of the shadow.
+    pub color: (u8, u8, u8, u8),
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            blur: 16,
+            offset: (0, 8),
+            color: (0, 0, 0, 128),
+        }
+    }
+}
+
+/// Bytes per row of a `BI_RGB` DIB: rows are padded to a 4-byte boundary.
+pub fn dib_stride(w: i32, depth: BitDepth) -> usize {
+    (w as usize * depth.bits() as usize).div_ceil(32) * 4
+}
+
+/// Post-processing and save settings shared by every capture mode.
+#[derive(Clone, Default)]
+pub struct Options {
+    pub brightness: i32, // -100..=100, 0 = unchanged
+    pub contrast: i32,   // -100..=100, 0 = unchanged
+    pub label: Option<String>,
+    pub label_pos: LabelPos,
+    pub scale: Option<u32>,         // percent, applied after color adjustments
+    pub max_dimension: Option<u32>, // downscale (after --scale) so neither side exceeds this
+    pub sharpen: Option<f64>,       // unsharp-mask strength, applied after scaling
+    pub thumbnail: Option<(i32, i32)>, // also write <name>.thumb.<ext> fitted in this box
+    pub thumb_format: Option<String>, // thumbnail extension; default: the main format
+    pub indexed: Option<u32>,       // 8bpp palette size (2..=256) for PNG/GIF output; not JPEG
+    pub bit_depth: BitDepth,
+    pub include_cursor: bool, // draw the mouse pointer into the capture
+    pub exclude_windows: Vec<String>, // titles of windows painted out of the capture
+    pub blur_regions: Vec<(i32, i32, i32, i32)>, // capture-relative areas to box-blur
+    pub blur_radius: Option<u32>, // px on each side, default 8
+    pub transparent_color: Option<(u8, u8, u8)>, // RGB made transparent in PNG/TIFF output
+    pub color_tolerance: f64, // max RGB distance still matching transparent_color
+    pub icc_profile: Option<Vec<u8>>, // embedded in PNG/JPEG/TIFF output
+    pub shadow: Option<Shadow>, // drop shadow on a transparent margin; PNG/TIFF only
+    pub keep_alpha: bool,     // keep the DIB's alpha channel (PNG/TIFF); off: opaque
+    pub no_gdiplus: bool,     // write a plain BMP without GDI+; other passes are skipped
+    pub no_atomic: bool,      // write the file in place instead of via <name>.tmp + rename
+    pub format: Option<String>, // encoder extension ("png"); overrides the filename's
+    pub quality: Option<u32>, // 0..=100 for lossy encoders (JPEG, WebP, HEIF)
+    pub png_compression: Option<u32>, // 0..=9 EncoderCompression for PNG; GDI+ may ignore it
+    pub raster_op: Option<u32>, // BitBlt ROP; None => SRCCOPY | CAPTUREBLT, or SRCCOPY over RDP
+    pub force_captureblt: bool, // keep CAPTUREBLT in the default ROP in remote sessions too
+    pub verbose: bool,        // diagnostics on stderr
+    pub quiet: bool,          // no warnings on stderr, only errors
+}
+
+// extension (".png") -> encoder CLSID, filled lazily so repeated saves skip enumeration.
+static CLSID_CACHE: OnceLock<Mutex<HashMap<String, GUID>>> = OnceLock::new();
+
+// number of live GDI+ startups; encoder enumeration is only valid while this is non-zero.
+static GDIP_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+// find a matching image encoder for an extension (like Gdip_SaveBitmapToFile does).
+fn clsid_for_extension(ext: &str) -> Result<GUID> {
+    // normalize the requested extension (".png", ".jpg", ...)
+    let want = format!(".{}", ext.trim_start_matches('.')).to_ascii_lowercase();
+    let cache = CLSID_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(clsid) = cache.lock().unwrap().get(&want) {
+        return Ok(*clsid);
+    }
+    if GDIP_STARTED.load(Ordering::Acquire) == 0 {
+        return Err(Error::new(
+            HRESULT(E_FAIL.0),
+            "GDI+ must be started before looking up encoders",
+        )
+        .into());
+    }
+    let clsid = find_encoder(&installed_encoders()?, &want)?.clsid;
+    cache.lock().unwrap().insert(want, clsid);
+    Ok(clsid)
+}
+
+// MIME type of the encoder for an extension ("image/png"); not cached, since it is only
+// needed once per in-memory encode.
+fn mime_type_for_extension(ext: &str) -> Result<String> {
+    let want = format!(".{}", ext.trim_start_matches('.')).to_ascii_lowercase();
+    Ok(find_encoder(&installed_encoders()?, &want)?
+        .mime_type
+        .clone())
+}
+
+// one installed encoder: its extension patterns ("*.JPG;*.JPEG;..."), CLSID and MIME type.
+struct EncoderInfo {
+    extensions: String,
+    clsid: GUID,
+    mime_type: String,
+}
+
+// copy out the installed image encoders; empty if GDI+ reports none.
+fn installed_encoders() -> Result<Vec<EncoderInfo>> {
+    let mut num = 0u32;
+    let mut size = 0u32;
+    unsafe {
+        if GdiPlus::GdipGetImageEncodersSize(&mut num, &mut size) != GdiPlus::Ok {
+            return Err(Error::new(HRESULT(E_FAIL.0), "GdipGetImageEncodersSize failed").into());
+        }
+    }
+    if num == 0 || size == 0 {
+        return Ok(Vec::new());
+    }
+    // aligned allocation
+    let encoders_ptr = unsafe { CoTaskMemAlloc(size as usize) } as *mut GdiPlus::ImageCodecInfo;
+    if encoders_ptr.is_null() {
+        return Err(Error::new(HRESULT(E_FAIL.0), "CoTaskMemAlloc failed").into());
+    }
+    // ensure free on all paths
+    let _encoders_guard = EncodersGuard(encoders_ptr as *mut c_void);
+    unsafe {
+        if GdiPlus::GdipGetImageEncoders(num, size, encoders_ptr) != GdiPlus::Ok {
+            return Err(Error::new(HRESULT(E_FAIL.0), "GdipGetImageEncoders failed").into());
+        }
+    }
+    // iterate the array portion at the beginning of the allocation. Each struct's pointer
+    // fields point into the same allocated block, so 'encoders_ptr' must stay alive until
+    // we finish.
+    let mut encoders = Vec::with_capacity(num as usize);
+    for i in 0..(num as usize) {
+        let info = unsafe { &*encoders_ptr.add(i) };
+        // some codecs may not provide FilenameExtension.
+        if info.FilenameExtension.is_null() {
+            continue;
+        }
+        // read the UTF-16 NUL-terminated string.
+        let p = PCWSTR::from_raw(info.FilenameExtension.0);
+        let mime_type = if info.MimeType.is_null() {
+            "application/octet-stream".to_string()
+        } else {
+            unsafe { PCWSTR::from_raw(info.MimeType.0).to_string() }.map_err(Error::from)?
+        };
+        encoders.push(EncoderInfo {
+            extensions: unsafe { p.to_string() }.map_err(Error::from)?,
+            clsid: info.Clsid,
+            mime_type,
+        });
+    }
+    Ok(encoders)
+}
+
+// pick the encoder whose patterns include `want` (lowercase, with leading dot). Kept free of
+// Win32 calls so the matching and the no-encoders case can be tested directly.
+fn find_encoder<'a>(encoders: &'a [EncoderInfo], want: &str) -> Result<&'a EncoderInfo> {
+    if encoders.is_empty() {
+        return Err(SnapshotError::NoEncodersInstalled);
+    }
+    for info in encoders {
+        // patterns look like "*.JPG;*.JPEG;*.JPE;*.JFIF".
+        for pat in info.extensions.split(';') {
+            let pat = pat.trim().trim_start_matches('*').to_ascii_lowercase(); // ".jpg"
+            if pat == want {
+                return Ok(info);
+            }
+        }
+    }
+    Err(SnapshotError::NoEncoder(
+        want.trim_start_matches('.').to_string(),
+    ))
+}
+
+fn gdip_startup() -> Result<usize> {
+    unsafe {
+        let mut input: GdiPlus::GdiplusStartupInput = zeroed();
+        input.GdiplusVersion = 1;
+        let mut token: usize = 0;
+        if GdiPlus::GdiplusStartup(
+            &mut token,
+            &input,
+            null_mut::<GdiPlus::GdiplusStartupOutput>(),
+        ) != GdiPlus::Ok
+        {
+            return Err(Error::new(HRESULT(E_FAIL.0), "GdiplusStartup failed").into());
+        }
+        GDIP_STARTED.fetch_add(1, Ordering::Release);
+        Ok(token)
+    }
+}
+
+/// Highest GDI+ API level the system accepts: "1.1" (startup version 2) or "1.0".
+/// `GdiplusStartupOutput` only carries notification hooks, so this probes startup directly.
+pub fn gdiplus_version() -> Result<&'static str> {
+    for (version, name) in [(2, "1.1"), (1, "1.0")] {
+        unsafe {
+            let mut input: GdiPlus::GdiplusStartupInputEx = zeroed();
+            input.Base.GdiplusVersion = version;
+            let mut output: GdiPlus::GdiplusStartupOutput = zeroed();
+            let mut token: usize = 0;
+            let status = GdiPlus::GdiplusStartup(
+                &mut token,
+                &input as *const _ as *const GdiPlus::GdiplusStartupInput,
+                &mut output,
+            );
+            if status == GdiPlus::Ok {
+                GdiPlus::GdiplusShutdown(token);
+                return Ok(name);
+            }
+        }
+    }
+    Err(Error::new(HRESULT(E_FAIL.0), "GdiplusStartup failed").into())
+}
+
+fn gdip_shutdown(token: usize) {
+    GDIP_STARTED.fetch_sub(1, Ordering::Release);
+    unsafe { GdiPlus::GdiplusShutdown(token) };
+}
+
+fn image_size(img: &ImgGuard) -> Result<(i32, i32)> {
+    let (mut w, mut h) = (0u32, 0u32);
+    unsafe {
+        gdip_check(
+            GdiPlus::GdipGetImageWidth(img.0, &mut w),
+            "GdipGetImageWidth",
+        )?;
+        gdip_check(
+            GdiPlus::GdipGetImageHeight(img.0, &mut h),
+            "GdipGetImageHeight",
+        )?;
+    }
+    Ok((w as i32, h as i32))
+}
+
+// brightness/contrast as a ColorMatrix: RGB is scaled around mid-grey by the contrast
+// factor, then shifted by the brightness offset. Rows are (R, G, B, A, translation).
+fn color_matrix(brightness: i32, contrast: i32) -> GdiPlus::ColorMatrix {
+    let scale = 1.0 + contrast as f32 / 100.0;
+    let offset = brightness as f32 / 100.0 + 0.5 * (1.0 - scale);
+    let mut cm = GdiPlus::ColorMatrix { m: [0.0; 25] };
+    for c in 0..3 {
+        cm.m[c * 5 + c] = scale;
+        cm.m[20 + c] = offset;
+    }
+    cm.m[18] = 1.0; // alpha passes through
+    cm.m[24] = 1.0;
+    cm
+}
+
+// redraw the image into a new bitmap through a brightness/contrast color matrix.
+fn adjust_colors(img: &ImgGuard, brightness: i32, contrast: i32) -> Result<ImgGuard> {
+    let (w, h) = image_size(img)?;
+    let matrix = color_matrix(brightness, contrast);
+    unsafe {
+        let mut attr = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateImageAttributes(&mut attr),
+            "GdipCreateImageAttributes",
+        )?;
+        let attr = AttrGuard(attr);
+        gdip_check(
+            GdiPlus::GdipSetImageAttributesColorMatrix(
+                attr.0,
+                GdiPlus::ColorAdjustTypeDefault,
+                true,
+                &matrix,
+                null(),
+                GdiPlus::ColorMatrixFlagsDefault,
+            ),
+            "GdipSetImageAttributesColorMatrix",
+        )?;
+        redraw(img, w, h, attr.0)
+    }
+}
+
+// convert in place to 8bpp with an optimal (octree) palette of at most `colors` entries,
+// error-diffusion dithered. Returns the number of palette entries actually used. A bitmap
+// with alpha keeps one transparent entry.
+fn to_indexed(img: &ImgGuard, colors: u32) -> Result<u32> {
+    let bmp = img.0 as *mut GdiPlus::GpBitmap;
+    let mut format = 0;
+    unsafe {
+        gdip_check(
+            GdiPlus::GdipGetImagePixelFormat(img.0, &mut format),
+            "GdipGetImagePixelFormat",
+        )?;
+    }
+    let alpha = format == PIXEL_FORMAT_32BPP_ARGB;
+    // ColorPalette is Flags, Count, then Count entries
+    let mut storage = vec![0u32; 2 + colors as usize];
+    let palette = storage.as_mut_ptr() as *mut GdiPlus::ColorPalette;
+    unsafe {
+        (*palette).Count = colors;
+        gdip_check(
+            GdiPlus::GdipInitializePalette(
+                palette,
+                GdiPlus::PaletteTypeOptimal,
+                colors as i32,
+                alpha,
+                bmp,
+            ),
+            "GdipInitializePalette",
+        )?;
+        gdip_check(
+            GdiPlus::GdipBitmapConvertFormat(
+                bmp,
+                PIXEL_FORMAT_8BPP_INDEXED,
+                GdiPlus::DitherTypeErrorDiffusion,
+                GdiPlus::PaletteTypeOptimal,
+                palette,
+                50.0, // alpha below this percentage maps to the transparent entry
+            ),
+            "GdipBitmapConvertFormat",
+        )?;
+        Ok((*palette).Count)
+    }
+}
+
+// resize by a percentage with high-quality bicubic filtering.
+fn scale_image(img: &ImgGuard, percent: u32) -> Result<ImgGuard> {
+    let (w, h) = image_size(img)?;
+    let sw = ((w as u64 * percent as u64 / 100) as i32).max(1);
+    let sh = ((h as u64 * percent as u64 / 100) as i32).max(1);
+    redraw(img, sw, sh, null())
+}
+
+// draw the whole image into a new dst_w x dst_h 32bpp bitmap (ARGB if the source is),
+// optionally through image attributes (null for none).
+fn redraw(
+    img: &ImgGuard,
+    dst_w: i32,
+    dst_h: i32,
+    attr: *const GdiPlus::GpImageAttributes,
+) -> Result<ImgGuard> {
+    let (w, h) = image_size(img)?;
+    unsafe {
+        // keep an alpha channel if the source has one
+        let mut src_format = 0;
+        GdiPlus::GdipGetImagePixelFormat(img.0, &mut src_format);
+        let format = if src_format == PIXEL_FORMAT_32BPP_ARGB {
+            PIXEL_FORMAT_32BPP_ARGB
+        } else {
+            PIXEL_FORMAT_32BPP_RGB
+        };
+        let mut out = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateBitmapFromScan0(dst_w, dst_h, 0, format, None, &mut out),
+            "GdipCreateBitmapFromScan0",
+        )?;
+        let out = ImgGuard(out as *mut GdiPlus::GpImage);
+        let mut g = null_mut();
+        gdip_check(
+            GdiPlus::GdipGetImageGraphicsContext(out.0, &mut g),
+            "GdipGetImageGraphicsContext",
+        )?;
+        let g = GraphicsGuard(g);
+        GdiPlus::GdipSetInterpolationMode(g.0, GdiPlus::InterpolationModeHighQualityBicubic);
+        gdip_check(
+            GdiPlus::GdipDrawImageRectRectI(
+                g.0,
+                img.0,
+                0,
+                0,
+                dst_w,
+                dst_h,
+                0,
+                0,
+                w,
+                h,
+                GdiPlus::UnitPixel,
+                attr,
+                0,
+                null_mut(),
+            ),
+            "GdipDrawImageRectRectI",
+        )?;
+        Ok(out)
+    }
+}
+
+// place the image on a larger transparent ARGB canvas over a blurred, offset copy of its
+// outline in the shadow color.
+fn add_shadow(img: &ImgGuard, shadow: Shadow) -> Result<ImgGuard> {
+    let (w, h) = image_size(img)?;
+    let blur = shadow.blur.min(1024) as i32;
+    let (ox, oy) = shadow.offset;
+    // room for the blur on every side, plus the offset on the side it points to
+    let margin = |size: i32, off: i32| {
+        off.checked_abs()
+            .and_then(|d| size.checked_add(2 * blur)?.checked_add(d))
+    };
+    let (Some(cw), Some(ch)) = (margin(w, ox), margin(h, oy)) else {
+        return Err(SnapshotError::InvalidOption(format!(
+            "shadow offset {ox},{oy} is too large"
+        )));
+    };
+    let (left, top) = (blur + ox.min(0).abs(), blur + oy.min(0).abs());
+    let (r, g, b, a) = shadow.color;
+    unsafe {
+        let mut out = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateBitmapFromScan0(cw, ch, 0, PIXEL_FORMAT_32BPP_ARGB, None, &mut out),
+            "GdipCreateBitmapFromScan0",
+        )?;
+        let out = ImgGuard(out as *mut GdiPlus::GpImage);
+        // transparent pixels carry the shadow color too, so blurring only fades the alpha
+        let (sx, sy) = (left + ox, top + oy);
+        pixels::lock_bits(&out, true, |buf, cw, ch, stride| {
+            for y in 0..ch {
+                for x in 0..cw {
+                    let inside = x >= sx && x < sx + w && y >= sy && y < sy + h;
+                    let p = y as usize * stride + x as usize * 4;
+                    buf[p..p + 4].copy_from_slice(&[b, g, r, if inside { a } else { 0 }]);
+                }
+            }
+        })?;
+        if blur > 0 {
+            pixels::box_blur(&out, (0, 0, cw, ch), blur as u32)?;
+        }
+        let mut gr = null_mut();
+        gdip_check(
+            GdiPlus::GdipGetImageGraphicsContext(out.0, &mut gr),
+            "GdipGetImageGraphicsContext",
+        )?;
+        let gr = GraphicsGuard(gr);
+        gdip_check(
+            GdiPlus::GdipDrawImageRectI(gr.0, img.0, left, top, w, h),
+            "GdipDrawImageRectI",
+        )?;
+        drop(gr);
+        Ok(out)
+    }
+}
+
+// stamp text onto the image in one corner, over a semi-transparent backing box.
+// The font size follows the image height so labels stay readable on large captures.
+fn draw_label(img: &ImgGuard, text: &str, pos: LabelPos) -> Result<()> {
+    let (w, h) = image_size(img)?;
+    let em = (h as f32 / 40.0).max(12.0);
+    let pad = em * 0.4;
+    let wtext = wide(text);
+    let family_name = wide("Segoe UI");
+    unsafe {
+        let mut g = null_mut();
+        gdip_check(
+            GdiPlus::GdipGetImageGraphicsContext(img.0, &mut g),
+            "GdipGetImageGraphicsContext",
+        )?;
+        let g = GraphicsGuard(g);
+        GdiPlus::GdipSetTextRenderingHint(g.0, GdiPlus::TextRenderingHintAntiAlias);
+
+        let mut family = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateFontFamilyFromName(
+                PCWSTR(family_name.as_ptr()),
+                null_mut(),
+                &mut family,
+            ),
+            "GdipCreateFontFamilyFromName",
+        )?;
+        let family = FontFamilyGuard(family);
+        let mut font = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateFont(
+                family.0,
+                em,
+                GdiPlus::FontStyleRegular.0,
+                GdiPlus::UnitPixel,
+                &mut font,
+            ),
+            "GdipCreateFont",
+        )?;
+        let font = FontGuard(font);
+        let mut format = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateStringFormat(0, 0, &mut format),
+            "GdipCreateStringFormat",
+        )?;
+        let format = StringFormatGuard(format);
+
+        // measure unconstrained, then place the box in the requested corner
+        let layout = GdiPlus::RectF {
+            X: 0.0,
+            Y: 0.0,
+            Width: w as f32,
+            Height: h as f32,
+        };
+        let mut bounds = GdiPlus::RectF::default();
+        gdip_check(
+            GdiPlus::GdipMeasureString(
+                g.0,
+                PCWSTR(wtext.as_ptr()),
+                -1,
+                font.0,
+                &layout,
+                format.0,
+                &mut bounds,
+                null_mut(),
+                null_mut(),
+            ),
+            "GdipMeasureString",
+        )?;
+        let box_w = bounds.Width + 2.0 * pad;
+        let box_h = bounds.Height + 2.0 * pad;
+        let (bx, by) = match pos {
+            LabelPos::TopLeft => (pad, pad),
+            LabelPos::TopRight => (w as f32 - box_w - pad, pad),
+            LabelPos::BottomLeft => (pad, h as f32 - box_h - pad),
+            LabelPos::BottomRight => (w as f32 - box_w - pad, h as f32 - box_h - pad),
+        };
+
+        let mut back = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateSolidFill(0xA000_0000, &mut back),
+            "GdipCreateSolidFill",
+        )?;
+        let back = BrushGuard(back as *mut GdiPlus::GpBrush);
+        gdip_check(
+            GdiPlus::GdipFillRectangle(g.0, back.0, bx, by, box_w, box_h),
+            "GdipFillRectangle",
+        )?;
+
+        let mut fore = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateSolidFill(0xFFFF_FFFF, &mut fore),
+            "GdipCreateSolidFill",
+        )?;
+        let fore = BrushGuard(fore as *mut GdiPlus::GpBrush);
+        let text_rect = GdiPlus::RectF {
+            X: bx + pad,
+            Y: by + pad,
+            Width: bounds.Width,
+            Height: bounds.Height,
+        };
+        gdip_check(
+            GdiPlus::GdipDrawString(
+                g.0,
+                PCWSTR(wtext.as_ptr()),
+                -1,
+                font.0,
+                &text_rect,
+                format.0,
+                fore.0,
+            ),
+            "GdipDrawString",
+        )?;
+    }
+    Ok(())
+}
+
+fn make_dib_section(
+    w: i32,
+    h: i32,
+    hdc_palette: Gdi::HDC,
+    depth: BitDepth,
+) -> Result<(Gdi::HBITMAP, *mut u8)> {
+    // 32bpp or 24bpp, bottom-up bitmap (positive height). Each row spans dib_stride(w, depth)
+    // bytes; for 24bpp that includes padding up to the next 4-byte boundary.
+    let mut bmi: Gdi::BITMAPINFO = unsafe { zeroed() };
+    bmi.bmiHeader.biSize = size_of::<Gdi::BITMAPINFOHEADER>() as u32;
+    bmi.bmiHeader.biWidth = w;
+    bmi.bmiHeader.biHeight = h; // positive => bottom-up
+    bmi.bmiHeader.biPlanes = 1;
+    bmi.bmiHeader.biBitCount = depth.bits();
+    bmi.bmiHeader.biCompression = Gdi::BI_RGB.0;
+    let mut bits: *mut core::ffi::c_void = null_mut();
+    // unwrap the Result<HBITMAP> here
+    let hbmp: Gdi::HBITMAP = unsafe {
+        Gdi::CreateDIBSection(
+            Some(hdc_palette),
+            &bmi,
+            Gdi::DIB_RGB_COLORS,
+            &mut bits,
+            None, // no file mapping
+            0,
+        )?
+    };
+    Ok((hbmp, bits as *mut u8))
+}
+
+/// Whether this process runs in a Remote Desktop session, where some policies make
+/// `CAPTUREBLT` screen copies come back entirely black.
+pub fn is_remote_session() -> bool {
+    unsafe { GetSystemMetrics(SM_REMOTESESSION) != 0 }
+}
+
+// the default screen ROP: SRCCOPY | CAPTUREBLT, which includes layered windows, but plain
+// SRCCOPY in a remote session unless `force_captureblt`.
+fn screen_raster_op(force_captureblt: bool) -> ROP_CODE {
+    if is_remote_session() && !force_captureblt {
+        SRCCOPY
+    } else {
+        SRCCOPY | CAPTUREBLT
+    }
+}
+
+// blit a screen rectangle into a new DIB section, honoring the capture-side options
+// (bit depth, raster op, cursor).
+fn capture_region(x: i32, y: i32, w: i32, h: i32, options: &Options) -> Result<Gdi::HBITMAP> {
+    let raster_op = options
+        .raster_op
+        .map_or_else(|| screen_raster_op(options.force_captureblt), ROP_CODE);
+    if is_remote_session() && options.verbose && options.raster_op.is_none() {
+        if options.force_captureblt {
+            eprintln!(
+                "remote session: keeping CAPTUREBLT (--force-captureblt); some RDP policies make \
+                 such captures come back black"
+            );
+        } else {
+            eprintln!(
+                "remote session: capturing with plain SRCCOPY, since some RDP policies make \
+                 CAPTUREBLT captures black; layered windows may be missing (--force-captureblt \
+                 keeps CAPTUREBLT)"
+            );
+        }
+    }
+    unsafe {
+        let hdc_screen = Gdi::GetDC(None);
+        if hdc_screen.0.is_null() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "GetDC failed").into());
+        }
+        let _screen_guard = ScreenDcGuard(hdc_screen);
+        if options.verbose {
+            let display_bits = Gdi::GetDeviceCaps(Some(hdc_screen), Gdi::BITSPIXEL);
+            if display_bits < 24 {
+                eprintln!(
+                    "warning: the display runs at {display_bits} bpp; the {}bpp capture only holds \
+                     the colors the display can show, so gradients may band",
+                    options.bit_depth.bits()
+                );
+                eprintln!(
+                    "         (--force-24/--force-32 pick the DIB format; neither restores color)"
+                );
+            }
+        }
+        let hbmp = blit_from_dc(hdc_screen, (x, y, w, h), options.bit_depth, raster_op)?;
+        if options.include_cursor || !options.exclude_windows.is_empty() {
+            let hbmp_guard = BitmapGuard(hbmp);
+            if options.include_cursor {
+                draw_cursor(hdc_screen, hbmp, x, y)?;
+            }
+            // last, so nothing (not even the pointer) shows through an excluded window
+            blank_windows(hdc_screen, hbmp, (x, y, w, h), options)?;
+            std::mem::forget(hbmp_guard);
+        }
+        Ok(hbmp)
+    }
+}
+
+// paint every window matching an --exclude-window title solid black in the captured bitmap,
+// whose top-left corresponds to screen (x, y). Window rectangles are clipped to the capture.
+fn blank_windows(
+    hdc_screen: Gdi::HDC,
+    hbmp: Gdi::HBITMAP,
+    capture: (i32, i32, i32, i32),
+    options: &Options,
+) -> Result<()> {
+    let mut rects = Vec::new();
+    for title in &options.exclude_windows {
+        for hwnd in window::find_windows_by_title(title) {
+            // minimized windows sit far off-screen and simply don't intersect
+            if let Some(r) = intersect_rect(window_rect(hwnd, false)?, capture) {
+                rects.push(GdiPlus::Rect {
+                    X: r.0 - capture.0,
+                    Y: r.1 - capture.1,
+                    Width: r.2,
+                    Height: r.3,
+                });
+            }
+        }
+    }
+    if options.verbose {
+        eprintln!("excluded {} window rectangle(s)", rects.len());
+    }
+    if rects.is_empty() {
+        return Ok(());
+    }
+    unsafe {
+        let mem_dc = Gdi::CreateCompatibleDC(Some(hdc_screen));
+        if mem_dc.0.is_null() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "CreateCompatibleDC failed").into());
+        }
+        let _mem_guard = DcGuard(mem_dc);
+        let old = Gdi::SelectObject(mem_dc, hbmp.into());
+        if old.is_invalid() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "SelectObject failed").into());
+        }
+        let _sel_guard = SelectGuard { dc: mem_dc, old };
+        // GDI+ drawing straight onto the DIB selected into the memory DC
+        let mut g = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateFromHDC(mem_dc, &mut g),
+            "GdipCreateFromHDC",
+        )?;
+        let g = GraphicsGuard(g);
+        let mut brush = null_mut();
+        gdip_check(
+            GdiPlus::GdipCreateSolidFill(0xFF00_0000, &mut brush),
+            "GdipCreateSolidFill",
+        )?;
+        let brush = BrushGuard(brush as *mut GdiPlus::GpBrush);
+        for r in &rects {
+            gdip_check(
+                GdiPlus::GdipFillRectangleI(g.0, brush.0, r.X, r.Y, r.Width, r.Height),
+                "GdipFillRectangleI",
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// BitBlt never includes the mouse pointer; draw it into the captured bitmap, whose top-left
+// corresponds to screen (x, y). Does nothing when the cursor is hidden.
+fn draw_cursor(hdc_screen: Gdi::HDC, hbmp: Gdi::HBITMAP, x: i32, y: i32) -> Result<()> {
+    unsafe {
+        let mut ci = CURSORINFO {
+            cbSize: size_of::<CURSORINFO>() as u32,
+            ..Default::default()
+        };
+        GetCursorInfo(&mut ci)?;
+        if ci.flags.0 & CURSOR_SHOWING.0 == 0 || ci.hCursor.is_invalid() {
+            return Ok(());
+        }
+        let mut ii = ICONINFO::default();
+        GetIconInfo(HICON(ci.hCursor.0), &mut ii)?;
+        // GetIconInfo hands us copies of the cursor bitmaps
+        let _mask = BitmapGuard(ii.hbmMask);
+        let _color = (!ii.hbmColor.is_invalid()).then(|| BitmapGuard(ii.hbmColor));
+
+        let mem_dc = Gdi::CreateCompatibleDC(Some(hdc_screen));
+        if mem_dc.0.is_null() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "CreateCompatibleDC failed").into());
+        }
+        let _mem_guard = DcGuard(mem_dc);
+        let old = Gdi::SelectObject(mem_dc, hbmp.into());
+        if old.is_invalid() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "SelectObject failed").into());
+        }
+        let _sel_guard = SelectGuard { dc: mem_dc, old };
+        Ok(DrawIconEx(
+            mem_dc,
+            ci.ptScreenPos.x - x - ii.xHotspot as i32,
+            ci.ptScreenPos.y - y - ii.yHotspot as i32,
+            HICON(ci.hCursor.0),
+            0,
+            0,
+            0,
+            None,
+            DI_NORMAL,
+        )?)
+    }
+}
+
+/// Copy the (x, y, w, h) area of any source DC (screen, printer, memory DC, ...) into a new
+/// bottom-up DIB section and return it. The caller owns the passed DC and must keep it valid
+/// for the duration of the call; the returned bitmap is owned by the caller and must be freed
+/// with `DeleteObject`.
+pub fn capture_from_dc(
+    src_dc: Gdi::HDC,
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+    depth: BitDepth,
+) -> Result<Gdi::HBITMAP> {
+    blit_from_dc(src_dc, (x, y, w, h), depth, SRCCOPY | CAPTUREBLT)
+}
+
+fn blit_from_dc(
+    src_dc: Gdi::HDC,
+    (x, y, w, h): (i32, i32, i32, i32),
+    depth: BitDepth,
+    raster_op: ROP_CODE,
+) -> Result<Gdi::HBITMAP> {
+    unsafe {
+        let mem_dc = Gdi::CreateCompatibleDC(Some(src_dc));
+        if mem_dc.0.is_null() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "CreateCompatibleDC failed").into());
+        }
+        let _mem_guard = DcGuard(mem_dc);
+
+        // create target bitmap (deleted automatically unless we forget it)
+        let (hbmp, _bits) = make_dib_section(w, h, src_dc, depth)?;
+        let hbmp_guard = BitmapGuard(hbmp);
+
+        // select it into mem DC; selection restored automatically
+        let old = Gdi::SelectObject(mem_dc, hbmp.into());
+        if old.is_invalid() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "SelectObject failed").into());
+        }
+        let _sel_guard = SelectGuard { dc: mem_dc, old };
+
+        // BitBlt from the source DC into our DIB
+        Gdi::BitBlt(mem_dc, 0, 0, w, h, Some(src_dc), x, y, raster_op)?;
+
+        // success: transfer ownership to caller (prevent guard from deleting it)
+        std::mem::forget(hbmp_guard);
+        Ok(hbmp)
+    }
+}
+
+// wrap HBITMAP -> GDI+ Bitmap and apply the post-processing passes from `options`.
+// `alpha` says whether the output format can store transparency. `hbmp` is only read, so
+// one capture can be prepared several times (bench, compare).
+fn prepare_image(hbmp: Gdi::HBITMAP, options: &Options, alpha: bool) -> Result<ImgGuard> {
+    let mut img = match options.transparent_color {
+        Some(key) if alpha => {
+            let img = pixels::argb_bitmap_from_dib(&pixels::dib_bits(hbmp)?)?;
+            pixels::color_key(&img, key, options.color_tolerance)?;
+            img
+        }
+        _ if alpha && options.keep_alpha => {
+            let img = pixels::argb_bitmap_from_dib(&pixels::dib_bits(hbmp)?)?;
+            // a screen BitBlt leaves alpha at zero: that means "no alpha", not "invisible"
+            if pixels::fill_empty_alpha(&img)? && options.verbose {
+                eprintln!("--keep-alpha: the capture has no alpha channel; saved opaque");
+            }
+            img
+        }
+        _ => {
+            let mut bmp: *mut GdiPlus::GpBitmap = null_mut();
+            unsafe {
+                if GdiPlus::GdipCreateBitmapFromHBITMAP(
+                    hbmp,
+                    Gdi::HPALETTE(std::ptr::null_mut()),
+                    &mut bmp,
+                ) != GdiPlus::Ok
+                {
+                    return Err(Error::new(
+                        HRESULT(E_FAIL.0),
+                        "GdipCreateBitmapFromHBITMAP failed",
+                    )
+                    .into());
+                }
+            }
+            // ensure dispose on all paths
+            ImgGuard(bmp as *mut GdiPlus::GpImage)
+        }
+    };
+    // blur regions are in capture coordinates, so before anything can resize the image
+    if !options.blur_regions.is_empty() {
+        let (w, h) = image_size(&img)?;
+        let radius = options.blur_radius.unwrap_or(8);
+        for &(x, y, rw, rh) in &options.blur_regions {
+            if rw <= 0 || rh <= 0 || x < 0 || y < 0 || x + rw > w || y + rh > h {
+                return Err(SnapshotError::InvalidRegion(format!(
+                    "blur region {x},{y},{rw},{rh} is not inside the {w}x{h} capture"
+                )));
+            }
+            pixels::box_blur(&img, (x, y, rw, rh), radius)?;
+        }
+    }
+    if options.brightness != 0 || options.contrast != 0 {
+        img = adjust_colors(&img, options.brightness, options.contrast)?;
+    }
+    if let Some(percent) = options.scale.filter(|&p| p != 100) {
+        img = scale_image(&img, percent)?;
+    }
+    if let Some(limit) = options.max_dimension {
+        let size = image_size(&img)?;
+        let limit = limit.min(i32::MAX as u32) as i32;
+        let (w, h) = fit_within(size, (limit, limit));
+        if (w, h) != size {
+            img = redraw(&img, w, h, null())?;
+        }
+    }
+    // after scaling, which is what softens the image
+    if let Some(amount) = options.sharpen {
+        pixels::sharpen(&img, amount)?;
+    }
+    // label last so its size follows the final image
+    if let Some(text) = &options.label {
+        draw_label(&img, text, options.label_pos)?;
+    }
+    // the shadow frames the finished image and needs alpha to show the desktop-free margin
+    if let Some(shadow) = options.shadow.filter(|_| alpha) {
+        img = add_shadow(&img, shadow)?;
+    }
+    Ok(img)
+}
+
+// formats whose GDI+ encoders keep an alpha channel.
+fn supports_alpha(ext: &str) -> bool {
+    matches!(ext.to_ascii_lowercase().as_str(), "png" | "tif" | "tiff")
+}
+
+// pick an encoder by the filename's extension.
+fn encoder_for_filename(filename: &str) -> Result<GUID> {
+    let ext = std::path::Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .ok_or_else(|| SnapshotError::NoEncoder(String::new()))?;
+    clsid_for_extension(ext)
+}
+
+// output format: `options.format` if set, else the file extension ("" when there is none,
+// as for pipe names).
+fn output_format<'a>(filename: &'a str, options: &'a Options) -> &'a str {
+    options.format.as_deref().unwrap_or_else(|| {
+        std::path::Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+    })
+}
+
+// wrap HBITMAP -> GDI+ Bitmap and apply everything that precedes the encoder for `ext`:
+// adjustments, palette reduction and ICC profile. a `thumbnail` box also returns a thumbnail
+// made from the full-color image.
+fn render_for_format(
+    hbmp: Gdi::HBITMAP,
+    ext: &str,
+    options: &Options,
+    thumbnail: Option<(i32, i32)>,
+) -> Result<(ImgGuard, Option<ImgGuard>)> {
+    let alpha = supports_alpha(ext) && options.bit_depth == BitDepth::Bits32;
+    if options.transparent_color.is_some() && !alpha && !options.quiet {
+        eprintln!(
+            "warning: --transparent-color needs 32bpp PNG or TIFF output; skipped for .{ext}"
+        );
+    }
+    if options.keep_alpha && !alpha && !options.quiet {
+        eprintln!("warning: --keep-alpha needs 32bpp PNG or TIFF output; skipped for .{ext}");
+    }
+    if options.shadow.is_some() && !alpha && !options.quiet {
+        eprintln!("warning: --shadow needs 32bpp PNG or TIFF output; skipped for .{ext}");
+    }
+    let img = prepare_image(hbmp, options, alpha)?;
+    // from the full-color image, before any palette reduction of the main output
+    let thumb = match thumbnail {
+        Some(size) => {
+            let (w, h) = fit_within(image_size(&img)?, size);
+            Some(redraw(&img, w, h, null())?)
+        }
+        None => None,
+    };
+    if let Some(colors) = options.indexed {
+        if ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg") {
+            if !options.quiet {
+                eprintln!("warning: JPEG cannot store a palette; --indexed skipped");
+            }
+        } else {
+            let used = to_indexed(&img, colors)?;
+            if options.verbose {
+                eprintln!("indexed color: {used}-entry palette (of {colors} allowed)");
+            }
+        }
+    }
+    if let Some(profile) = &options.icc_profile {
+        if icc::carries_icc(ext) {
+            icc::attach_icc(&img, profile)?;
+        } else if options.verbose {
+            eprintln!("skipping ICC profile: .{ext} output cannot carry one");
+        }
+    }
+    Ok((img, thumb))
+}
+
+// render, choose encoder by format, save to a file or, for \\.\pipe\ names, write the
+// encoded bytes to the pipe
+fn save_hbitmap_with_gdiplus(hbmp: Gdi::HBITMAP, filename: &str, options: &Options) -> Result<()> {
+    let ext = output_format(filename, options);
+    if ext.is_empty() {
+        return Err(SnapshotError::NoEncoder(String::new()));
+    }
+    let thumbnail = options.thumbnail.filter(|_| !pipe::is_pipe_path(filename));
+    let (img, thumb) = render_for_format(hbmp, ext, options, thumbnail)?;
+    let clsid = clsid_for_extension(ext)?;
+    if options.quality.is_some() && !takes_quality(ext) && !options.quiet {
+        eprintln!(
+            "warning: --quality applies to lossy formats (JPEG, WebP, HEIF); ignored for .{ext}"
+        );
+    }
+    if options.png_compression.is_some() && !is_png(ext) && !options.quiet {
+        eprintln!("warning: --png-compression applies to PNG; ignored for .{ext}");
+    }
+    let setting = encoder_setting(ext, options);
+    if pipe::is_pipe_path(filename) {
+        return pipe::write_to_pipe(filename, &encode_image(&img, &clsid, setting)?);
+    }
+    write_atomically(filename, !options.no_atomic, |path| {
+        save_image(&img, path, &clsid, setting)
+    })?;
+    if let Some(thumb) = thumb {
+        let thumb_ext = options.thumb_format.as_deref().unwrap_or(ext);
+        let thumb_file = thumbnail_filename(filename, thumb_ext);
+        let clsid = clsid_for_extension(thumb_ext)?;
+        let setting = encoder_setting(thumb_ext, options);
+        write_atomically(&thumb_file, !options.no_atomic, |path| {
+            save_image(&thumb, path, &clsid, setting)
+        })?;
+        if options.verbose {
+            let (w, h) = image_size(&thumb)?;
+            eprintln!("thumbnail: {thumb_file} ({w}x{h})");
+        }
+    }
+    Ok(())
+}
+
+// write `filename` through `write`: with `atomic`, into a sibling <name>.tmp that is renamed
+// over the target once it is complete (a same-volume rename, atomic on NTFS), so watchers never
+// see a half-written image and a failed encode leaves any previous file untouched. the temp
+// file is removed if writing or the rename fails.
+fn write_atomically(
+    filename: &str,
+    atomic: bool,
+    write: impl FnOnce(&str) -> Result<()>,
+) -> Result<()> {
+    if !atomic {
+        return write(filename);
+    }
+    let tmp = format!("{filename}.tmp");
+    let result = write(&tmp).and_then(|()| Ok(std::fs::rename(&tmp, filename)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+// largest size with the aspect ratio of `size` that fits in `bounds`; never upscales.
+fn fit_within(size: (i32, i32), bounds: (i32, i32)) -> (i32, i32) {
+    let (w, h) = (size.0 as f64, size.1 as f64);
+    let ratio = (bounds.0 as f64 / w).min(bounds.1 as f64 / h).min(1.0);
+    (
+        ((w * ratio).round() as i32).max(1),
+        ((h * ratio).round() as i32).max(1),
+    )
+}
+
+// "shots/a.png" + "jpg" -> "shots/a.thumb.jpg"
+fn thumbnail_filename(filename: &str, ext: &str) -> String {
+    let path = std::path::Path::new(filename);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    path.with_file_name(format!("{stem}.thumb.{ext}"))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn save_image(
+    img: &ImgGuard,
+    filename: &str,
+    clsid: &GUID,
+    setting: Option<EncoderSetting>,
+) -> Result<()> {
+    let wname = wide_path(filename);
+    let mut value = setting.map_or(0, |(_, v)| v);
+    let params = setting.map(|(guid, _)| long_param(guid, &mut value));
+    let params = params.as_ref().map_or(null(), |p| p as *const _);
+    let status =
+        unsafe { GdiPlus::GdipSaveImageToFile(img.0, PCWSTR(wname.as_ptr()), clsid, params) };
+    save_check(status, "GdipSaveImageToFile", filename)
+}
+
+// an encoder parameter GUID and its Long value: EncoderQuality for lossy formats,
+// EncoderCompression for PNG. no format takes both, so one is all a save ever passes.
+type EncoderSetting = (GUID, u32);
+
+// one Long encoder parameter; `value` must outlive the GDI+ call using the result.
+fn long_param(guid: GUID, value: &mut u32) -> GdiPlus::EncoderParameters {
+    GdiPlus::EncoderParameters {
+        Count: 1,
+        Parameter: [GdiPlus::EncoderParameter {
+            Guid: guid,
+            NumberOfValues: 1,
+            Type: GdiPlus::EncoderParameterValueTypeLong.0 as u32,
+            Value: value as *mut u32 as *mut c_void,
+        }],
+    }
+}
+
+// formats with a lossy encoder that takes EncoderQuality.
+fn takes_quality(ext: &str) -> bool {
+    matches!(
+        ext.to_ascii_lowercase().as_str(),
+        "jpg" | "jpeg" | "jpe" | "jfif" | "webp" | "heic" | "heif"
+    )
+}
+
+fn is_png(ext: &str) -> bool {
+    ext.eq_ignore_ascii_case("png")
+}
+
+// options.quality or options.png_compression, whichever the encoder for `ext` uses.
+fn encoder_setting(ext: &str, options: &Options) -> Option<EncoderSetting> {
+    if takes_quality(ext) {
+        return options.quality.map(|q| (GdiPlus::EncoderQuality, q));
+    }
+    if is_png(ext) {
+        return options
+            .png_compression
+            .map(|c| (GdiPlus::EncoderCompression, c));
+    }
+    None
+}
+
+// fallback for systems without GDI+ encoders: dump the DIB as an uncompressed BMP.
+fn save_hbitmap_as_bmp(hbmp: Gdi::HBITMAP, filename: &str, options: &Options) -> Result<()> {
+    let ext = std::path::Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    if !ext.eq_ignore_ascii_case("bmp") {
+        return Err(SnapshotError::NoEncoder(ext.to_string())); // only BMP is written without GDI+
+    }
+    let mut bits = pixels::dib_bits(hbmp)?;
+    write_atomically(filename, !options.no_atomic, |path| {
+        pixels::write_bmp(&mut bits, path)
+    })
+}
+
+// encode to an in-memory IStream and copy the bytes out.
+fn encode_image(img: &ImgGuard, clsid: &GUID, setting: Option<EncoderSetting>) -> Result<Vec<u8>> {
+    let mut value = setting.map_or(0, |(_, v)| v);
+    let params = setting.map(|(guid, _)| long_param(guid, &mut value));
+    let params = params.as_ref().map_or(null(), |p| p as *const _);
+    unsafe {
+        let stream = CreateStreamOnHGlobal(HGLOBAL::default(), true)?;
+        gdip_check(
+            GdiPlus::GdipSaveImageToStream(img.0, &stream, clsid, params),
+            "GdipSaveImageToStream",
+        )?;
+        // the stream position after saving is the encoded length
+        let mut len = 0u64;
+        stream.Seek(0, STREAM_SEEK_CUR, Some(&mut len))?;
+        let hglobal = GetHGlobalFromStream(&stream)?;
+        let ptr = GlobalLock(hglobal) as *const u8;
+        if ptr.is_null() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "GlobalLock failed").into());
+        }
+        let bytes = std::slice::from_raw_parts(ptr, len as usize).to_vec();
+        let _ = GlobalUnlock(hglobal);
+        Ok(bytes)
+    }
+}
+
+// resolve a monitor handle to its full rectangle (x, y, w, h) in virtual-desktop coordinates.
+fn monitor_rect(hmon: Gdi::HMONITOR) -> Result<(i32, i32, i32, i32)> {
+    let mut info: Gdi::MONITORINFO = unsafe { zeroed() };
+    info.cbSize = size_of::<Gdi::MONITORINFO>() as u32;
+    if !unsafe { Gdi::GetMonitorInfoW(hmon, &mut info) }.as_bool() {
+        return Err(Error::new(HRESULT(E_FAIL.0), "GetMonitorInfoW failed").into());
+    }
+    let r = info.rcMonitor;
+    Ok((r.left, r.top, r.right - r.left, r.bottom - r.top))
+}
+
+/// Scale factor in percent (100, 125, 150, ...) of the monitor holding most of `rect`, and
+/// that monitor's rectangle.
+pub fn monitor_scale(rect: (i32, i32, i32, i32)) -> Result<(u32, (i32, i32, i32, i32))> {
+    let (x, y, w, h) = rect;
+    let r = RECT {
+        left: x,
+        top: y,
+        right: x + w,
+        bottom: y + h,
+    };
+    let hmon = unsafe { Gdi::MonitorFromRect(&r, Gdi::MONITOR_DEFAULTTONEAREST) };
+    let scale = unsafe { GetScaleFactorForMonitor(hmon)? };
+    Ok((scale.0 as u32, monitor_rect(hmon)?))
+}
+
+/// Scale a rectangle by `num / den` about `origin`, e.g. DIPs to physical pixels with
+/// `(scale, 100)` about the monitor's top-left corner, or back with `(100, scale)`.
+pub fn scale_rect(
+    rect: (i32, i32, i32, i32),
+    origin: (i32, i32),
+    num: u32,
+    den: u32,
+) -> (i32, i32, i32, i32) {
+    let s = |v: i32| (v as i64 * num as i64 / den as i64) as i32;
+    let (x, y, w, h) = rect;
+    let (ox, oy) = origin;
+    (ox + s(x - ox), oy + s(y - oy), s(w), s(h))
+}
+
+/// Intersection of two (x, y, w, h) rectangles, or `None` if they don't overlap or an edge
+/// lies beyond the `i32` range.
+pub fn intersect_rect(
+    a: (i32, i32, i32, i32),
+    b: (i32, i32, i32, i32),
+) -> Option<(i32, i32, i32, i32)> {
+    let left = a.0.max(b.0);
+    let top = a.1.max(b.1);
+    let right = a.0.checked_add(a.2)?.min(b.0.checked_add(b.2)?);
+    let bottom = a.1.checked_add(a.3)?.min(b.1.checked_add(b.3)?);
+    (right > left && bottom > top).then_some((left, top, right - left, bottom - top))
+}
+
+// fail unless the right and bottom edges of (x, y, w, h) fit in an i32.
+fn check_rect_edges((x, y, w, h): (i32, i32, i32, i32)) -> Result<()> {
+    match (x.checked_add(w), y.checked_add(h)) {
+        (Some(_), Some(_)) => Ok(()),
+        _ => Err(SnapshotError::InvalidRegion(format!(
+            "region {x},{y} {w}x{h} reaches past the coordinate range"
+        ))),
+    }
+}
+
+/// The part of a window rectangle that lies on a monitor, for capturing a window that
+/// straddles displays one display at a time. Fails if the window is entirely elsewhere.
+pub fn window_on_monitor(
+    window: (i32, i32, i32, i32),
+    monitor: (i32, i32, i32, i32),
+) -> Result<(i32, i32, i32, i32)> {
+    intersect_rect(window, monitor).ok_or_else(|| {
+        let (x, y, w, h) = monitor;
+        SnapshotError::InvalidRegion(format!(
+            "window {},{} {}x{} does not overlap monitor {x},{y} {w}x{h}",
+            window.0, window.1, window.2, window.3
+        ))
+    })
+}
+
+/// Move a rectangle the least distance that puts it inside `bounds`, keeping its size. A
+/// rectangle larger than `bounds` in some dimension is cut down to it there. Fails if an edge of
+/// either rectangle lies beyond the `i32` range.
+pub fn slide_into(
+    rect: (i32, i32, i32, i32),
+    bounds: (i32, i32, i32, i32),
+) -> Result<(i32, i32, i32, i32)> {
+    check_rect_edges(rect)?;
+    check_rect_edges(bounds)?;
+    let (bx, by, bw, bh) = bounds;
+    let w = rect.2.min(bw);
+    let h = rect.3.min(bh);
+    let x = rect.0.clamp(bx, bx + bw - w);
+    let y = rect.1.clamp(by, by + bh - h);
+    Ok((x, y, w, h))
+}
+
+/// Clip a requested rectangle to the virtual desktop bounds. Coordinates are virtual-desktop
+/// coordinates, where monitors left of or above the primary have negative x/y.
+pub fn clamp_to_desktop(
+    req: (i32, i32, i32, i32),
+    desktop: (i32, i32, i32, i32),
+) -> Result<(i32, i32, i32, i32)> {
+    check_rect_edges(req)?;
+    intersect_rect(req, desktop).ok_or_else(|| {
+        let (x, y, w, h) = desktop;
+        SnapshotError::InvalidRegion(format!(
+            "region {},{} {}x{} lies outside the virtual desktop {x},{y} {w}x{h}",
+            req.0, req.1, req.2, req.3
+        ))
+    })
+}
+
+/// Returns (x, y, w, h) for the chosen screen mode.
+pub fn screen_rect(mode: ScreenMode) -> Result<(i32, i32, i32, i32)> {
+    Ok(match mode {
+        ScreenMode::Virtual => {
+            // entire virtual desktop (spans all monitors; x/y can be negative)
+            let x = unsafe { GetSystemMetrics(SM_XVIRTUALSCREEN) };
+            let y = unsafe { GetSystemMetrics(SM_YVIRTUALSCREEN) };
+            let w = unsafe { GetSystemMetrics(SM_CXVIRTUALSCREEN) };
+            let h = unsafe { GetSystemMetrics(SM_CYVIRTUALSCREEN) };
+            (x, y, w, h)
+        }
+        ScreenMode::Primary => {
+            // primary monitor only. its rcMonitor rather than (0, 0, SM_CXSCREEN, SM_CYSCREEN):
+            // the same rectangle on a single monitor, but the metrics are cached per process
+            // at the primary's DPI of the time and don't follow per-monitor DPI or
+            // resolution changes, which left parts of the primary uncaptured (or captured
+            // the neighbour) on mixed-DPI setups
+            let hmon = unsafe {
+                Gdi::MonitorFromPoint(POINT { x: 0, y: 0 }, Gdi::MONITOR_DEFAULTTOPRIMARY)
+            };
+            monitor_rect(hmon)?
+        }
+        ScreenMode::MonitorAt(point) => {
+            // monitor containing the point (or the cursor), falling back to the nearest one
+            let (x, y) = match point {
+                Some(p) => p,
+                None => cursor_pos()?,
+            };
+            let pt = POINT { x, y };
+            let hmon = unsafe { Gdi::MonitorFromPoint(pt, Gdi::MONITOR_DEFAULTTONEAREST) };
+            monitor_rect(hmon)?
+        }
+    })
+}
+
+/// Current mouse position in virtual-desktop coordinates.
+pub fn cursor_pos() -> Result<(i32, i32)> {
+    let mut pt = POINT::default();
+    unsafe { GetCursorPos(&mut pt)? };
+    Ok((pt.x, pt.y))
+}
+
+/// Which screen area a mode-based capture covers.
+#[derive(Clone, Copy)]
+pub enum ScreenMode {
+    Virtual,
+    Primary,
+    // None => current cursor position
+    MonitorAt(Option<(i32, i32)>),
+}
+
+/// A running GDI+ instance for capturing many times without paying startup/shutdown per
+/// shot. GDI+ is shut down when the session is dropped.
+pub struct Session {
+    _gdip: GdiplusGuard,
+    backend: Box<dyn backend::CaptureBackend>,
+}
+
+impl Session {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            _gdip: GdiplusGuard::new()?,
+            backend: Box::new(backend::GdiBackend),
+        })
+    }
+
+    /// A session whose captures come from the DWM thumbnail of `hwnd` (its client area with
+    /// `client_only`) rather than a plain screen copy. DWM composes the thumbnail into a
+    /// topmost helper window laid over the window for the duration of each capture, so
+    /// covered windows and some GPU-rendered ones capture correctly. Rectangles passed to this
+    /// session must lie within the window; protected content still comes back black.
+    pub fn with_dwm_thumbnail(hwnd: HWND, client_only: bool) -> Result<Self> {
+        Ok(Self {
+            _gdip: GdiplusGuard::new()?,
+            backend: Box::new(backend::DwmThumbnailBackend { hwnd, client_only }),
+        })
+    }
+
+    // a session whose captures come from `backend` instead of the screen.
+    #[cfg(test)]
+    pub(crate) fn with_backend(backend: Box<dyn backend::CaptureBackend>) -> Result<Self> {
+        Ok(Self {
+            _gdip: GdiplusGuard::new()?,
+            backend,
+        })
+    }
+
+    /// Start an animated GIF at `filename` with `frames` frames shown `delay` apart. Frames are
+    /// added with [`GifWriter::add_frame`] and the file is completed by [`GifWriter::finish`].
+    pub fn gif(&self, filename: &str, delay: Duration, frames: u32) -> Result<GifWriter<'_>> {
+        GifWriter::new(self, filename, delay, frames)
+    }
+
+    /// Time `frames` captures of `rect` (x, y, w, h), then `frames` in-memory encodes of one capture
+    /// with the encoder for `ext`. Nothing is written to disk.
+    pub fn bench(
+        &self,
+        rect: (i32, i32, i32, i32),
+        frames: u32,
+        ext: &str,
+        options: &Options,
+    ) -> Result<BenchReport> {
+        bench::run(self.backend.as_ref(), rect, frames, ext, options)
+    }
+
+    /// Capture `rect` (x, y, w, h) with `options` applied and compare it pixel by pixel with
+    /// the image file `reference`, which must have the same dimensions. With `diff_out`, an
+    /// image marking the differing pixels in red is written there.
+    pub fn compare(
+        &self,
+        rect: (i32, i32, i32, i32),
+        reference: &str,
+        diff_out: Option<&str>,
+        options: &Options,
+    ) -> Result<CompareReport> {
+        compare::run(self.backend.as_ref(), rect, reference, diff_out, options)
+    }
+
+    /// Encode a 1x1 capture with every installed encoder, decode each result again and check
+    /// its size: a smoke test of the GDI+ install that writes no files. One entry per encoder.
+    pub fn selftest(&self) -> Result<Vec<FormatCheck>> {
+        selftest::run(self.backend.as_ref())
+    }
+
+    /// Capture `rect` (x, y, w, h) and encode it in memory with the encoder for `format`
+    /// ("png", "jpg", ...), applying `options` as for a file save except the thumbnail. Always
+    /// goes through GDI+, whatever `options.no_gdiplus` says.
+    pub fn capture_encoded(
+        &self,
+        rect: (i32, i32, i32, i32),
+        format: &str,
+        options: &Options,
+    ) -> Result<EncodedImage> {
+        capture_with(self.backend.as_ref(), rect, options, false, |hbmp| {
+            encode_hbitmap(hbmp, format, options)
+        })
+        .map(|(image, _)| image)
+    }
+
+    /// Run a [`CaptureRequest`] in this session, like [`capture`] without the GDI+ startup.
+    pub fn capture_request(&self, request: &CaptureRequest) -> Result<CaptureResult> {
+        capture::run(self, request)
+    }
+
+    /// Capture the screen rectangle (x, y, w, h) and save it to `filename`; the encoder is
+    /// chosen from the file extension.
+    pub fn capture(
+        &self,
+        x: i32,
+        y: i32,
+        w: i32,
+        h: i32,
+        filename: &str,
+        options: &Options,
+    ) -> Result<()> {
+        capture_with(
+            self.backend.as_ref(),
+            (x, y, w, h),
+            options,
+            false,
+            |hbmp| save_hbitmap(hbmp, filename, options),
+        )
+        .map(|_| ())
+    }
+}
+
+// capture `rect` through `backend`, hand the bitmap to `save` and delete it afterwards; with
+// `check_black`, also report whether the capture came back entirely black.
+pub(crate) fn capture_with<T>(
+    backend: &dyn backend::CaptureBackend,
+    rect: (i32, i32, i32, i32),
+    options: &Options,
+    check_black: bool,
+    save: impl FnOnce(Gdi::HBITMAP) -> Result<T>,
+) -> Result<(T, bool)> {
+    let hbmp = backend.capture(rect, options)?;
+    let all_black = check_black && {
+        // make sure GDI has finished writing the bits before reading them directly
+        let _ = unsafe { Gdi::GdiFlush() };
+        pixels::dib_bits(hbmp).is_ok_and(|bits| pixels::is_all_black(&bits))
+    };
+    let result = save(hbmp);
+    unsafe {
+        let _ = Gdi::DeleteObject(hbmp.into());
+    }
+    Ok((result?, all_black))
+}
+
+// save a captured bitmap to `filename`, through GDI+ or, with `no_gdiplus`, as a plain BMP.
+pub(crate) fn save_hbitmap(hbmp: Gdi::HBITMAP, filename: &str, options: &Options) -> Result<()> {
+    if options.no_gdiplus {
+        save_hbitmap_as_bmp(hbmp, filename, options)
+    } else {
+        save_hbitmap_with_gdiplus(hbmp, filename, options)
+    }
+}
+
+// encode a captured bitmap in memory with the encoder for `format`.
+pub(crate) fn encode_hbitmap(
+    hbmp: Gdi::HBITMAP,
+    format: &str,
+    options: &Options,
+) -> Result<EncodedImage> {
+    let clsid = clsid_for_extension(format)?;
+    let mime_type = mime_type_for_extension(format)?;
+    let (img, _) = render_for_format(hbmp, format, options, None)?;
+    Ok(EncodedImage {
+        bytes: encode_image(&img, &clsid, encoder_setting(format, options))?,
+        mime_type,
+    })
+}
+
+/// Capture the screen rectangle (x, y, w, h) and save it to `filename`; the encoder is
+/// chosen from the file extension. GDI+ is started and shut down for the duration of the call,
+/// unless `options.no_gdiplus` asks for the GDI-only BMP fallback.
+pub fn capture_rectangle(
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+    filename: &str,
+    options: &Options,
+) -> Result<()> {
+    if options.no_gdiplus {
+        return capture_with(&backend::GdiBackend, (x, y, w, h), options, false, |hbmp| {
+            save_hbitmap_as_bmp(hbmp, filename, options)
+        })
+        .map(|_| ());
+    }
+    Session::new()?.capture(x, y, w, h, filename, options)
+}
+
+/// The output format a capture to `filename` with `options` would be written in ("png", "jpg",
+/// ...), after checking that an encoder for it is installed. Nothing is captured or written;
+/// GDI+ is started only for the lookup, and not at all with `options.no_gdiplus`.
+pub fn encoder_format(filename: &str, options: &Options) -> Result<String> {
+    if options.no_gdiplus {
+        let ext = std::path::Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("");
+        if !ext.eq_ignore_ascii_case("bmp") {
+            return Err(SnapshotError::NoEncoder(ext.to_string()));
+        }
+        return Ok(ext.to_ascii_lowercase());
+    }
+    let ext = output_format(filename, options);
+    if ext.is_empty() {
+        return Err(SnapshotError::NoEncoder(String::new()));
+    }
+    let _gdip = GdiplusGuard::new()?;
+    clsid_for_extension(ext)?;
+    Ok(ext.to_ascii_lowercase())
+}
+
+/// Async wrapper around [`capture_rectangle`] for Tokio services. The blocking GDI work,
+/// including GDI+ startup and shutdown, runs entirely inside a `spawn_blocking` task.
+#[cfg(feature = "async")]
+pub async fn capture_rectangle_async(
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+    filename: String,
+    options: Options,
+) -> Result<()> {
+    tokio::task::spawn_blocking(move || capture_rectangle(x, y, w, h, &filename, &options))
+        .await
+        .map_err(|e| Error::new(HRESULT(E_FAIL.0), format!("capture task failed: {e}")))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{fake_image, fake_session};
+
+    // average of one channel over the whole image
+    fn mean(img: &ImgGuard, channel: usize) -> f64 {
+        pixels::lock_bits(img, false, |buf, w, h, stride| {
+            let mut sum = 0u64;
+            for y in 0..h as usize {
+                for x in 0..w as usize {
+                    sum += buf[y * stride + x * 4 + channel] as u64;
+                }
+            }
+            sum as f64 / (w as u64 * h as u64) as f64
+        })
+        .unwrap()
+    }
+
+    // left monitor 1280x1024 at -1280,0; primary 1920x1080 at 0,0
+    const DESKTOP: (i32, i32, i32, i32) = (-1280, 0, 3200, 1080);
+
+    #[test]
+    fn no_installed_encoders_is_a_distinct_error() {
+        assert!(matches!(
+            find_encoder(&[], ".png"),
+            Err(SnapshotError::NoEncodersInstalled)
+        ));
+    }
+
+    #[test]
+    fn encoder_patterns_match_case_insensitively() {
+        let clsid = GUID::from_u128(0x557cf406_1a04_11d3_9a73_0000f81ef32e);
+        let encoders = [EncoderInfo {
+            extensions: "*.JPG;*.JPEG;*.JPE;*.JFIF".into(),
+            clsid,
+            mime_type: "image/jpeg".into(),
+        }];
+        let found = find_encoder(&encoders, ".jpe").unwrap();
+        assert_eq!(
+            (found.clsid, found.mime_type.as_str()),
+            (clsid, "image/jpeg")
+        );
+        assert!(find_encoder(&encoders, ".png").is_err());
+    }
+
+    #[test]
+    fn optional_codec_patterns_match_and_their_absence_names_alternatives() {
+        let encoders = [
+            EncoderInfo {
+                extensions: "*.WEBP".into(),
+                clsid: GUID::from_u128(1),
+                mime_type: "image/webp".into(),
+            },
+            EncoderInfo {
+                extensions: "*.HEIC; *.HEIF".into(),
+                clsid: GUID::from_u128(2),
+                mime_type: "image/heif".into(),
+            },
+        ];
+        assert_eq!(
+            find_encoder(&encoders, ".webp").unwrap().clsid,
+            GUID::from_u128(1)
+        );
+        assert_eq!(
+            find_encoder(&encoders, ".heif").unwrap().clsid,
+            GUID::from_u128(2)
+        );
+        let Err(missing) = find_encoder(&encoders[1..], ".webp") else {
+            panic!("the HEIF encoder must not match .webp");
+        };
+        assert!(missing.to_string().contains(".png"), "{missing}");
+    }
+
+    #[test]
+    fn negative_x_region_inside_desktop_is_unchanged() {
+        let req = (-1000, 100, 800, 600);
+        assert_eq!(clamp_to_desktop(req, DESKTOP).unwrap(), req);
+    }
+
+    #[test]
+    fn region_spilling_off_the_left_edge_is_clipped() {
+        let req = (-1500, -50, 500, 300);
+        assert_eq!(
+            clamp_to_desktop(req, DESKTOP).unwrap(),
+            (-1280, 0, 280, 250)
+        );
+    }
+
+    #[test]
+    fn overlapping_rectangles_intersect() {
+        assert_eq!(
+            intersect_rect((0, 0, 100, 80), (50, 20, 100, 100)),
+            Some((50, 20, 50, 60))
+        );
+        let inner = (10, 10, 20, 20);
+        assert_eq!(intersect_rect(inner, (0, 0, 100, 100)), Some(inner));
+    }
+
+    #[test]
+    fn touching_or_separate_rectangles_do_not_intersect() {
+        assert_eq!(intersect_rect((0, 0, 100, 100), (100, 0, 50, 50)), None);
+        assert_eq!(intersect_rect((0, 0, 100, 100), (0, 100, 50, 50)), None);
+        assert_eq!(intersect_rect((0, 0, 10, 10), (-50, -50, 5, 5)), None);
+        assert_eq!(intersect_rect((i32::MAX - 10, 0, 20, 10), DESKTOP), None);
+    }
+
+    #[test]
+    fn window_straddling_two_monitors_is_cut_at_the_edge() {
+        let window = (-400, 100, 1000, 600);
+        assert_eq!(
+            window_on_monitor(window, (-1280, 0, 1280, 1024)).unwrap(),
+            (-400, 100, 400, 600)
+        );
+        assert_eq!(
+            window_on_monitor(window, (0, 0, 1920, 1080)).unwrap(),
+            (0, 100, 600, 600)
+        );
+        assert!(window_on_monitor(window, (1920, 0, 1920, 1080)).is_err());
+    }
+
+    #[test]
+    fn box_past_the_right_edge_slides_back_at_full_size() {
+        assert_eq!(
+            slide_into((1800, 900, 400, 300), DESKTOP).unwrap(),
+            (1520, 780, 400, 300)
+        );
+    }
+
+    #[test]
+    fn box_inside_the_desktop_does_not_move() {
+        let r = (-1000, 100, 400, 300);
+        assert_eq!(slide_into(r, DESKTOP).unwrap(), r);
+    }
+
+    #[test]
+    fn box_larger_than_the_desktop_is_cut_to_it() {
+        assert_eq!(
+            slide_into((0, 0, 4000, 300), DESKTOP).unwrap(),
+            (-1280, 0, 3200, 300)
+        );
+    }
+
+    #[test]
+    fn box_past_the_i32_range_is_rejected() {
+        assert!(matches!(
+            slide_into((1000, 0, i32::MAX, 300), DESKTOP),
+            Err(SnapshotError::InvalidRegion(_))
+        ));
+    }
+
+    #[test]
+    fn dips_scale_about_the_monitor_origin() {
+        // 150% monitor to the right of a 1920px primary
+        assert_eq!(
+            scale_rect((1920 + 100, 200, 400, 300), (1920, 0), 150, 100),
+            (1920 + 150, 300, 600, 450)
+        );
+        assert_eq!(
+            scale_rect((2070, 300, 600, 450), (1920, 0), 100, 150),
+            (2020, 200, 400, 300)
+        );
+    }
+
+    #[test]
+    fn thumbnails_keep_the_aspect_ratio() {
+        assert_eq!(fit_within((1920, 1080), (320, 320)), (320, 180));
+        assert_eq!(fit_within((1080, 1920), (320, 320)), (180, 320));
+        assert_eq!(fit_within((100, 50), (320, 240)), (100, 50)); // no upscaling
+        assert_eq!(
+            thumbnail_filename("shots/a.png", "jpg"),
+            std::path::Path::new("shots/a.thumb.jpg").to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn long_paths_get_the_extended_length_prefix() {
+        assert_eq!(
+            extended_length_path(r"C:\shots\a.png"),
+            r"\\?\C:\shots\a.png"
+        );
+        assert_eq!(
+            extended_length_path(r"\\nas\share\スクショ.png"),
+            r"\\?\UNC\nas\share\スクショ.png"
+        );
+        assert_eq!(extended_length_path(r"\\?\D:\x.png"), r"\\?\D:\x.png");
+        // short paths are passed through untouched
+        assert_eq!(wide_path("写真.png"), wide("写真.png"));
+    }
+
+    #[test]
+    fn extension_inference_handles_non_ascii_names() {
+        let options = Options::default();
+        assert_eq!(
+            output_format(r"C:\写真\スクリーンショット.PNG", &options),
+            "PNG"
+        );
+        assert_eq!(output_format(r"C:\写真.d\名前", &options), "");
+    }
+
+    #[test]
+    fn region_outside_desktop_is_rejected() {
+        assert!(clamp_to_desktop((-3000, 0, 500, 500), DESKTOP).is_err());
+        assert!(clamp_to_desktop((1920, 0, 100, 100), DESKTOP).is_err());
+    }
+
+    #[test]
+    fn region_past_the_i32_range_is_rejected() {
+        for req in [(2147483000, 0, 1000, 10), (0, i32::MAX, 10, 1)] {
+            assert!(matches!(
+                clamp_to_desktop(req, DESKTOP),
+                Err(SnapshotError::InvalidRegion(msg)) if msg.contains("coordinate range")
+            ));
+        }
+    }
+
+    #[test]
+    fn failed_saves_are_io_errors() {
+        let session = fake_session();
+        let path = std::env::temp_dir().join(format!(
+            "gdip_snapshot_test_{}_missing_dir",
+            std::process::id()
+        ));
+        let file = path.join("shot.png");
+        assert!(matches!(
+            session.capture(0, 0, 8, 8, file.to_str().unwrap(), &Options::default()),
+            Err(SnapshotError::Io(_))
+        ));
+        assert!(matches!(
+            save_check(GdiPlus::AccessDenied, "GdipSaveImageToFile", "x.png"),
+            Err(SnapshotError::Io(e)) if e.kind() == std::io::ErrorKind::PermissionDenied
+        ));
+    }
+
+    #[test]
+    fn shadow_adds_a_transparent_margin() {
+        let _session = fake_session();
+        let bmp = fake_image(40, 30, &Options::default());
+        let options = Options {
+            shadow: Some(Shadow {
+                blur: 4,
+                offset: (3, -2),
+                color: (0, 0, 0, 200),
+            }),
+            ..Default::default()
+        };
+        let img = prepare_image(bmp.0, &options, true).unwrap();
+        assert_eq!(image_size(&img).unwrap(), (40 + 8 + 3, 30 + 8 + 2));
+        let (corner, inside) = pixels::lock_bits(&img, false, |buf, _, _, stride| {
+            (buf[3], buf[10 * stride + 20 * 4 + 3])
+        })
+        .unwrap();
+        assert_eq!((corner, inside), (0, 255));
+    }
+
+    #[test]
+    fn brightness_raises_every_channel() {
+        let _session = fake_session();
+        let bmp = fake_image(32, 32, &Options::default());
+        let plain = prepare_image(bmp.0, &Options::default(), false).unwrap();
+        let brighter = Options {
+            brightness: 40,
+            ..Default::default()
+        };
+        let adjusted = prepare_image(bmp.0, &brighter, false).unwrap();
+        for channel in 0..3 {
+            assert!(mean(&adjusted, channel) > mean(&plain, channel));
+        }
+    }
+
+    #[test]
+    fn keep_alpha_preserves_real_alpha_and_ignores_empty_alpha() {
+        let _session = fake_session();
+        let options = Options {
+            keep_alpha: true,
+            ..Default::default()
+        };
+        let alpha_at_origin = |fill: Option<u8>| {
+            let bmp = fake_image(8, 8, &Options::default());
+            if let Some(a) = fill {
+                let mut bits = pixels::dib_bits(bmp.0).unwrap();
+                bits.bytes_mut()
+                    .chunks_exact_mut(4)
+                    .for_each(|px| px[3] = a);
+            }
+            let img = prepare_image(bmp.0, &options, true).unwrap();
+            pixels::lock_bits(&img, false, |buf, _, _, _| buf[3]).unwrap()
+        };
+        assert_eq!(alpha_at_origin(Some(100)), 100);
+        assert_eq!(alpha_at_origin(None), 255); // BitBlt-style all-zero alpha
+    }
+
+    #[test]
+    fn encoder_is_chosen_by_extension() {
+        let _session = fake_session();
+        let bmp = fake_image(16, 16, &Options::default());
+        let img = prepare_image(bmp.0, &Options::default(), false).unwrap();
+        let jpeg = encode_image(&img, &clsid_for_extension("jpg").unwrap(), None).unwrap();
+        assert!(jpeg.starts_with(&[0xFF, 0xD8]));
+        let bmp_file = encode_image(&img, &clsid_for_extension(".BMP").unwrap(), None).unwrap();
+        assert!(bmp_file.starts_with(b"BM"));
+        assert!(matches!(
+            clsid_for_extension("xyz"),
+            Err(SnapshotError::NoEncoder(ext)) if ext == "xyz"
+        ));
+    }
+
+    #[test]
+    fn png_compression_levels_still_decode() {
+        let _session = fake_session();
+        let bmp = fake_image(24, 16, &Options::default());
+        let img = prepare_image(bmp.0, &Options::default(), false).unwrap();
+        let clsid = clsid_for_extension("png").unwrap();
+        for level in [0, 6, 9] {
+            let options = Options {
+                png_compression: Some(level),
+                ..Default::default()
+            };
+            let png = encode_image(&img, &clsid, encoder_setting("png", &options)).unwrap();
+            assert!(png.starts_with(b"\x89PNG"), "level {level}");
+            assert_eq!(
+                selftest::decoded_size(&png).unwrap(),
+                (24, 16),
+                "level {level}"
+            );
+        }
+    }
+}
@@ -1,369 +1,1848 @@
 use std::env;
-use std::ffi::{OsStr, c_void};
-use std::iter::once;
-use std::mem::{size_of, zeroed};
-use std::os::windows::ffi::OsStrExt;
-use std::ptr::{null, null_mut};
-
-use windows::Win32::Foundation::{E_FAIL, E_INVALIDARG};
-use windows::Win32::Graphics::Gdi;
-use windows::Win32::Graphics::Gdi::{CAPTUREBLT, ROP_CODE, SRCCOPY};
-use windows::Win32::Graphics::GdiPlus;
-use windows::Win32::System::Com::{CoTaskMemAlloc, CoTaskMemFree};
-use windows::Win32::UI::WindowsAndMessaging::{
-    GetSystemMetrics, SM_CXSCREEN, SM_CXVIRTUALSCREEN, SM_CYSCREEN, SM_CYVIRTUALSCREEN,
-    SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN,
+use std::ffi::c_void;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use windows::Win32::Foundation::{CloseHandle, HANDLE, HWND};
+use windows::Win32::System::Console::{CTRL_BREAK_EVENT, CTRL_C_EVENT, SetConsoleCtrlHandler};
+use windows::Win32::System::IO::CancelSynchronousIo;
+use windows::Win32::System::Threading::{GetCurrentThreadId, OpenThread, THREAD_TERMINATE};
+use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_CMONITORS};
+use windows::core::BOOL;
+
+use gdip_snapshot::{
+    BitDepth, CaptureOptions, CaptureRequest, CaptureResult, LabelPos, Options, PhaseStats, Result,
+    ScreenMode, Session, Shadow, SnapshotError, WindowSelector, capture, capture_monitors,
+    clamp_to_desktop, cursor_pos, encoder_format, find_window, gdiplus_version, is_pipe_path,
+    is_remote_session, monitor_by_name, monitor_rects, monitor_scale, pad_rect, parse_hwnd,
+    scale_rect, screen_rect, slide_into, srgb_icc_profile, sub_rect, wait_for_window,
+    window_on_monitor, window_rect,
 };
-use windows::core::{Error, GUID, HRESULT, PCWSTR};
 
-fn wide<S: AsRef<OsStr>>(s: S) -> Vec<u16> {
-    s.as_ref().encode_wide().chain(once(0)).collect()
+type Rect = (i32, i32, i32, i32); // x, y, w, h
+
+// parse "<x>,<y>,<w>,<h>".
+fn parse_rect(s: &str) -> Option<Rect> {
+    let v: Vec<i32> = s
+        .split(',')
+        .map(|p| p.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match v[..] {
+        [x, y, w, h] => Some((x, y, w, h)),
+        _ => None,
+    }
 }
 
-struct EncodersGuard(*mut c_void);
-impl Drop for EncodersGuard {
-    fn drop(&mut self) {
-        unsafe { CoTaskMemFree(Some(self.0)) }
+// normalize two opposite corners "x1,y1,x2,y2" (in either order) into x, y, w, h. x2/y2
+// are exclusive like a Win32 RECT's right/bottom, so equal coordinates give no area.
+fn corners_rect((x1, y1, x2, y2): Rect) -> Option<Rect> {
+    let (x, y) = (x1.min(x2), y1.min(y2));
+    let w = x1.max(x2).checked_sub(x)?;
+    let h = y1.max(y2).checked_sub(y)?;
+    (w > 0 && h > 0).then_some((x, y, w, h))
+}
+
+// parse "#RRGGBB" (the "#" is optional).
+fn parse_color(s: &str) -> Option<(u8, u8, u8)> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
     }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
 }
 
-struct ScreenDcGuard(Gdi::HDC);
-impl Drop for ScreenDcGuard {
-    fn drop(&mut self) {
-        unsafe {
-            Gdi::ReleaseDC(None, self.0);
-        }
+// far beyond any useful shadow, and small enough that the canvas size can't overflow.
+const MAX_SHADOW_OFFSET: i32 = 4096;
+
+// parse "#RRGGBB" or "#RRGGBBAA"; without alpha the color is half transparent.
+fn parse_shadow_color(s: &str) -> Option<(u8, u8, u8, u8)> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() == 8 && hex.is_ascii() {
+        let (r, g, b) = parse_color(&hex[..6])?;
+        return Some((r, g, b, u8::from_str_radix(&hex[6..], 16).ok()?));
     }
+    parse_color(hex).map(|(r, g, b)| (r, g, b, 128))
 }
 
-struct DcGuard(Gdi::HDC);
-impl Drop for DcGuard {
-    fn drop(&mut self) {
-        unsafe {
-            let _ = Gdi::DeleteDC(self.0);
-        }
+// parse "<x>,<y>" or "cursor" for --monitor-at.
+fn parse_point(s: &str) -> Option<Option<(i32, i32)>> {
+    if s.eq_ignore_ascii_case("cursor") {
+        return Some(None);
     }
+    let (x, y) = s.split_once(',')?;
+    Some(Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
 }
 
-struct BitmapGuard(Gdi::HBITMAP);
-impl Drop for BitmapGuard {
-    fn drop(&mut self) {
-        unsafe {
-            let _ = Gdi::DeleteObject(self.0.into());
+// "400x300" -> (400, 300)
+fn parse_size(s: &str) -> Option<(i32, i32)> {
+    let (w, h) = s.split_once(['x', 'X'])?;
+    let (w, h) = (w.trim().parse().ok()?, h.trim().parse().ok()?);
+    (w > 0 && h > 0).then_some((w, h))
+}
+
+// "NOTSRCCOPY", "0x00CC0020" or several joined with '|' ("SRCCOPY|CAPTUREBLT")
+fn parse_rop(s: &str) -> Option<u32> {
+    const KNOWN: &[(&str, u32)] = &[
+        ("SRCCOPY", 0x00CC_0020),
+        ("SRCPAINT", 0x00EE_0086),
+        ("SRCAND", 0x0088_00C6),
+        ("SRCINVERT", 0x0066_0046),
+        ("SRCERASE", 0x0044_0328),
+        ("NOTSRCCOPY", 0x0033_0008),
+        ("NOTSRCERASE", 0x0011_00A6),
+        ("MERGECOPY", 0x00C0_00CA),
+        ("MERGEPAINT", 0x00BB_0226),
+        ("PATCOPY", 0x00F0_0021),
+        ("PATPAINT", 0x00FB_0A09),
+        ("PATINVERT", 0x005A_0049),
+        ("DSTINVERT", 0x0055_0009),
+        ("BLACKNESS", 0x0000_0042),
+        ("WHITENESS", 0x00FF_0062),
+        ("NOMIRRORBITMAP", 0x8000_0000),
+        ("CAPTUREBLT", 0x4000_0000),
+    ];
+    s.split('|').try_fold(0, |rop, part| {
+        let part = part.trim();
+        let value = match part.strip_prefix("0x").or_else(|| part.strip_prefix("0X")) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => {
+                KNOWN
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(part))?
+                    .1
+            }
+        };
+        Some(rop | value)
+    })
+}
+
+fn usage() {
+    eprintln!("Usage:");
+    eprintln!("  gdip_snapshot <x> <y> <width> <height> <output_file>");
+    eprintln!("  gdip_snapshot --full <output_file>     # all monitors (virtual desktop)");
+    eprintln!("  gdip_snapshot --primary <output_file>  # primary monitor only");
+    eprintln!("  gdip_snapshot --monitor-at <x>,<y> <output_file>  # monitor containing a point");
+    eprintln!("  gdip_snapshot --monitor-at cursor <output_file>   # monitor under the mouse");
+    eprintln!(
+        "  gdip_snapshot --monitor-name <\\\\.\\DISPLAY1> <output_file>  # monitor by device name"
+    );
+    eprintln!("  gdip_snapshot --around-cursor <w>x<h> <output_file>  # box centered on the mouse");
+    eprintln!(
+        "  gdip_snapshot --each-monitor [--serial] <output_file>  # <name>_0001.<ext> per monitor"
+    );
+    eprintln!(
+        "  gdip_snapshot --corners <x1>,<y1>,<x2>,<y2> <output_file>  # two opposite corners"
+    );
+    eprintln!("  gdip_snapshot --dip <x> <y> <width> <height> <output_file>  # rectangle in DIPs");
+    eprintln!("  gdip_snapshot <output_file>            # default: --primary");
+    eprintln!("  gdip_snapshot --window <title> | --hwnd <handle> | --foreground <output_file>");
+    eprintln!("      [--client-only] [--window-region <x>,<y>,<w>,<h>]  # window or part of it");
+    eprintln!("  gdip_snapshot --wait-for-window <title> [--wait-timeout <s>] <output_file>");
+    eprintln!("      # poll until the window exists (default 30 s), then capture it like --window");
+    eprintln!("      [--padding <px>]  # grow (or shrink, if negative) the window rectangle");
+    eprintln!(
+        "      [--shadow [--shadow-blur <px>] [--shadow-offset <x>,<y>] [--shadow-color <#RRGGBBAA>]]"
+    );
+    eprintln!("      # drop shadow on a transparent margin (PNG/TIFF)");
+    eprintln!("      [--dwm-thumbnail]  # from the window's DWM thumbnail, even when covered");
+    eprintln!("      [--rect-from-window-and-monitor <n|device>]  # only the part on that monitor");
+    eprintln!(
+        "  gdip_snapshot --stdin-coords  # read \"x y w h filename\" lines, print OK/ERR each"
+    );
+    eprintln!(
+        "  ... --interval <s> [--count <n>]  # repeat; frames saved as <name>_0001.<ext>, ..."
+    );
+    eprintln!("  ... --interval <s> --count <n> --gif <file.gif>  # frames as one animated GIF");
+    eprintln!(
+        "  ... --interval <s> --progress  # frame count, elapsed and remaining time on stderr"
+    );
+    eprintln!(
+        "  ... --bench [--frames <n>] [<file.ext>]  # time capture and encode (nothing saved)"
+    );
+    eprintln!(
+        "  ... --compare <reference> [--threshold <pct>] [--diff-out <file>]  # assert a match"
+    );
+    eprintln!(
+        "  gdip_snapshot [mode] --temp [--format <ext>]  # save to a new %TEMP% file, print path"
+    );
+    eprintln!(
+        "  gdip_snapshot [mode] --base64 [--data-uri] [--format <ext>]  # image on stdout, no file"
+    );
+    eprintln!("  gdip_snapshot [mode] --dry-run <output_file>  # print rectangles, names, encoder");
+    eprintln!("  gdip_snapshot --selftest  # encode/decode a 1x1 capture with every encoder");
+    eprintln!("  gdip_snapshot --version | -V  # version, target, GDI+ level, monitor count");
+    eprintln!("Options:");
+    eprintln!("  --brightness <-100..100>  --contrast <-100..100>");
+    eprintln!("  --verbose, -v  # print diagnostics to stderr");
+    eprintln!("  --json  # print file, scale and physical/logical rectangles as JSON on stdout");
+    eprintln!("  --quiet, -q  # no warnings on stderr, only errors");
+    eprintln!("  --scale <percent>  # resize the output");
+    eprintln!("  --max-dimension <px>  # downscale only if the longest side is larger");
+    eprintln!("  --keep-alpha  # PNG/TIFF: keep the capture's alpha channel instead of opaque");
+    eprintln!("  --quality <0..100>  # lossy encoders (JPEG, WebP, HEIF); smaller = smaller files");
+    eprintln!("  --png-compression <0..9>  # PNG: 9 = smallest, slowest (not all GDI+ versions)");
+    eprintln!(
+        "  --blur-region <x>,<y>,<w>,<h> [--blur-radius <px>]  # blur part of it (repeatable)"
+    );
+    eprintln!("  --append-dimensions  # shot.png -> shot_1920x1080.png (captured size)");
+    eprintln!("  --exclude-window <title>  # black out matching windows (repeatable)");
+    eprintln!("  --thumbnail <w>x<h> [--thumb-format <ext>]  # also write <name>.thumb.<ext>");
+    eprintln!("  --indexed [colors]  # 8-bit palette output for PNG/GIF, default 256 colors");
+    eprintln!("  --sharpen <0..10>  # unsharp mask after scaling, e.g. 0.5");
+    eprintln!("  --bit-depth 24|32 | --force-24 | --force-32  # capture DIB depth, default 32");
+    eprintln!("  --icc <profile.icc> | --srgb  # embed a color profile (PNG/JPEG/TIFF)");
+    eprintln!(
+        "  --cursor | --no-cursor  # draw the mouse pointer (default: on for screen modes only)"
+    );
+    eprintln!("  --transparent-color #RRGGBB [--tolerance <n>]  # color key for PNG/TIFF alpha");
+    eprintln!("  --rop <name|0xHEX>[|...]  # BitBlt raster op, default SRCCOPY|CAPTUREBLT");
+    eprintln!("  --force-captureblt  # keep CAPTUREBLT in remote (RDP) sessions too");
+    eprintln!(
+        "  --format png|jpg|bmp|gif|tiff  # encoder; required for \\\\.\\pipe\\<name> output"
+    );
+    eprintln!("  --no-gdiplus  # write a plain .bmp via GDI only (no encoders or post-processing)");
+    eprintln!("  --no-atomic  # write the output in place instead of via <name>.tmp + rename");
+    eprintln!("  --label <text> [--label-pos tl|tr|bl|br]  # stamp text, default bottom-right");
+}
+
+/// What to capture.
+enum Target {
+    Rect(i32, i32, i32, i32),
+    Screen(ScreenMode),
+    // monitor by GDI device name ("\\.\DISPLAY2")
+    MonitorName(String),
+    // w x h box centered on the mouse pointer
+    AroundCursor(i32, i32),
+    // one file per monitor; serial => one monitor after another on the main thread
+    EachMonitor {
+        serial: bool,
+    },
+    // "x y w h filename" lines on stdin, one capture per line
+    StdinCoords,
+    Window {
+        selector: WindowSelector,
+        client_only: bool,
+        region: Option<Rect>,
+        padding: i32,
+        wait: Option<Duration>, // --wait-for-window: poll for the title this long
+        dwm_thumbnail: bool,    // capture from the window's DWM thumbnail
+        monitor: Option<String>, // only the part on this monitor (number or device name)
+    },
+}
+
+/// Parsed command line.
+struct Cli {
+    target: Target,
+    filename: String,
+    options: Options,
+    interval: Option<Duration>,
+    count: Option<u32>,       // None with an interval => until killed
+    gif: bool,                // filename is an animated GIF collecting every interval frame
+    bench: Option<u32>,       // frames to time; filename only picks the encoder
+    compare: Option<Compare>, // filename is the reference image
+    dip: bool,                // explicit rectangle is in device-independent pixels
+    json: bool,               // print a JSON summary of the capture on stdout
+    temp: bool,               // filename is chosen in %TEMP% at run time and printed
+    append_dimensions: bool,  // insert "_<w>x<h>" before the extension
+    dry_run: bool,            // print the resolved plan instead of capturing
+    base64: bool,             // write the encoded image to stdout as base64, no file
+    data_uri: bool,           // with base64: as a data:<mime>;base64,... URI
+    progress: bool,           // status line on stderr during --interval runs
+}
+
+/// --compare settings.
+struct Compare {
+    threshold: f64, // largest differing-pixel percentage that still passes
+    diff_out: Option<String>,
+}
+
+// exit codes; stable, scripts depend on them (see README)
+const EXIT_MISMATCH: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_INVALID_REGION: i32 = 3;
+const EXIT_NO_ENCODER: i32 = 4;
+const EXIT_CAPTURE: i32 = 5;
+const EXIT_IO: i32 = 6;
+const EXIT_TIMEOUT: i32 = 7;
+const EXIT_INTERRUPTED: i32 = 8;
+
+// set by the Ctrl+C handler; batch loops finish the frame in hand and stop
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// true while --stdin-coords blocks reading the next line, with no capture in progress.
+static WAITING_FOR_INPUT: AtomicBool = AtomicBool::new(false);
+
+// the --stdin-coords worker thread (a handle opened for CancelSynchronousIo), or null.
+static STDIN_READER: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+
+unsafe extern "system" fn on_console_ctrl(ctrl_type: u32) -> BOOL {
+    if ctrl_type == CTRL_C_EVENT || ctrl_type == CTRL_BREAK_EVENT {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        // the worker would otherwise sit in its read until the producer sends more. keep
+        // cancelling until it leaves the read: a console read that Ctrl+C aborted is retried
+        // by std, and the retry may start after the first cancel.
+        let reader = HANDLE(STDIN_READER.load(Ordering::SeqCst));
+        while !reader.is_invalid() && WAITING_FOR_INPUT.load(Ordering::SeqCst) {
+            let _ = unsafe { CancelSynchronousIo(reader) };
+            std::thread::sleep(Duration::from_millis(10));
         }
+        return true.into(); // handled: don't terminate the process mid-save
     }
+    false.into()
 }
 
-struct SelectGuard {
-    dc: Gdi::HDC,
-    old: Gdi::HGDIOBJ,
+// batch modes only; single captures keep the default Ctrl+C behavior
+fn handle_ctrl_c() {
+    let _ = unsafe { SetConsoleCtrlHandler(Some(on_console_ctrl), true) };
 }
-impl Drop for SelectGuard {
-    fn drop(&mut self) {
-        unsafe {
-            Gdi::SelectObject(self.dc, self.old);
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// sleep in short steps so Ctrl+C doesn't wait out a long interval.
+fn sleep_unless_interrupted(d: Duration) {
+    let until = Instant::now() + d;
+    while !interrupted() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
         }
+        std::thread::sleep(left.min(Duration::from_millis(100)));
     }
 }
 
-struct GdiplusGuard(usize);
-impl GdiplusGuard {
-    fn new() -> windows::core::Result<Self> {
-        gdip_startup().map(Self)
+fn exit_code(e: &SnapshotError) -> i32 {
+    match e {
+        SnapshotError::InvalidRegion(_) => EXIT_INVALID_REGION,
+        SnapshotError::InvalidOption(_) => EXIT_USAGE,
+        SnapshotError::NoEncoder(_) | SnapshotError::NoEncodersInstalled => EXIT_NO_ENCODER,
+        SnapshotError::Io(_) => EXIT_IO,
+        SnapshotError::Timeout(_) => EXIT_TIMEOUT,
+        SnapshotError::Windows(_) => EXIT_CAPTURE,
     }
 }
-impl Drop for GdiplusGuard {
-    fn drop(&mut self) {
-        gdip_shutdown(self.0);
-    }
+
+// usage error: bad flags or values
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(EXIT_USAGE);
 }
 
-struct ImgGuard(*mut GdiPlus::GpImage);
-impl Drop for ImgGuard {
-    fn drop(&mut self) {
-        if !self.0.is_null() {
-            unsafe { GdiPlus::GdipDisposeImage(self.0) };
-        }
-    }
+fn next_value<'a>(it: &mut impl Iterator<Item = &'a String>, flag: &str) -> &'a str {
+    it.next()
+        .map(|s| s.as_str())
+        .unwrap_or_else(|| fail(&format!("{flag} expects a value")))
+}
+
+fn parse_num<T: std::str::FromStr>(s: &str, what: &str) -> T {
+    s.parse()
+        .unwrap_or_else(|_| fail(&format!("{what} must be an integer")))
 }
 
-// find a matching image encoder for an extension (like Gdip_SaveBitmapToFile does).
-fn clsid_for_extension(ext: &str) -> windows::core::Result<GUID> {
-    let mut num = 0u32;
-    let mut size = 0u32;
-    unsafe {
-        if GdiPlus::GdipGetImageEncodersSize(&mut num, &mut size) != GdiPlus::Ok {
-            return Err(Error::new(
-                HRESULT(E_FAIL.0),
-                "GdipGetImageEncodersSize failed",
-            ));
+fn parse_args(args: &[String]) -> Cli {
+    let mut mode = None;
+    let mut window = None;
+    let mut client_only = false;
+    let mut region = None;
+    let mut padding = 0;
+    let mut wait_for_window = false;
+    let mut wait_timeout = None;
+    let mut around = None;
+    let mut rect = None; // --corners
+    let mut monitor_name = None;
+    let mut shadow = false;
+    let mut shadow_style = Shadow::default();
+    let mut shadow_tuned = false; // any --shadow-* given
+    let mut each_monitor = false;
+    let mut dip = false;
+    let mut temp = false;
+    let mut append_dimensions = false;
+    let mut dry_run = false;
+    let mut base64 = false;
+    let mut progress = false;
+    let mut data_uri = false;
+    let mut dwm_thumbnail = false;
+    let mut on_monitor = None;
+    let mut json = false;
+    let mut serial = false;
+    let mut stdin_coords = false;
+    let mut interval = None;
+    let mut count = None;
+    let mut gif = None;
+    let mut bench = false;
+    let mut cursor = None;
+    let mut frames = 50;
+    let mut compare = None;
+    let mut threshold = 0.0;
+    let mut diff_out = None;
+    let mut options = Options::default();
+    let mut positional: Vec<&str> = Vec::new();
+    let mut it = args.iter().skip(1).peekable();
+    while let Some(arg) = it.next() {
+        let new_mode = match arg.as_str() {
+            "--full" => ScreenMode::Virtual,
+            "--primary" => ScreenMode::Primary,
+            "--monitor-at" => {
+                let point = parse_point(next_value(&mut it, arg)).unwrap_or_else(|| {
+                    fail("--monitor-at expects <x>,<y> or cursor");
+                });
+                ScreenMode::MonitorAt(point)
+            }
+            "--window" | "--hwnd" | "--foreground" => {
+                let sel = match arg.as_str() {
+                    "--window" => WindowSelector::Title(next_value(&mut it, arg).to_string()),
+                    "--hwnd" => {
+                        WindowSelector::Hwnd(parse_hwnd(next_value(&mut it, arg)).unwrap_or_else(
+                            || fail("--hwnd expects a hex (0x...) or decimal handle"),
+                        ))
+                    }
+                    _ => WindowSelector::Foreground,
+                };
+                if window.replace(sel).is_some() {
+                    fail("only one of --window, --hwnd or --foreground may be given");
+                }
+                continue;
+            }
+            "--wait-for-window" => {
+                let sel = WindowSelector::Title(next_value(&mut it, arg).to_string());
+                if window.replace(sel).is_some() {
+                    fail("only one of --window, --hwnd or --foreground may be given");
+                }
+                wait_for_window = true;
+                continue;
+            }
+            "--wait-timeout" => {
+                let secs: f64 = next_value(&mut it, arg)
+                    .parse()
+                    .unwrap_or_else(|_| fail("--wait-timeout expects seconds"));
+                if !(secs >= 0.0 && secs.is_finite()) {
+                    fail("--wait-timeout must be >= 0");
+                }
+                wait_timeout = Some(
+                    Duration::try_from_secs_f64(secs)
+                        .unwrap_or_else(|_| fail("--wait-timeout is out of range")),
+                );
+                continue;
+            }
+            "--padding" => {
+                padding = parse_num(next_value(&mut it, arg), "padding");
+                continue;
+            }
+            "--interval" => {
+                let secs: f64 = next_value(&mut it, arg)
+                    .parse()
+                    .unwrap_or_else(|_| fail("--interval expects seconds"));
+                if !(secs > 0.0 && secs.is_finite()) {
+                    fail("--interval must be > 0");
+                }
+                interval = Some(
+                    Duration::try_from_secs_f64(secs)
+                        .unwrap_or_else(|_| fail("--interval is out of range")),
+                );
+                continue;
+            }
+            "--count" => {
+                let n: u32 = parse_num(next_value(&mut it, arg), "count");
+                if n == 0 {
+                    fail("--count must be > 0");
+                }
+                count = Some(n);
+                continue;
+            }
+            "--gif" => {
+                gif = Some(next_value(&mut it, arg));
+                continue;
+            }
+            "--scale" => {
+                let percent: u32 = parse_num(next_value(&mut it, arg), "scale");
+                if percent == 0 {
+                    fail("--scale must be > 0");
+                }
+                options.scale = Some(percent);
+                continue;
+            }
+            "--max-dimension" => {
+                let px: u32 = parse_num(next_value(&mut it, arg), "max-dimension");
+                if px == 0 {
+                    fail("--max-dimension must be > 0");
+                }
+                options.max_dimension = Some(px);
+                continue;
+            }
+            "--sharpen" => {
+                let amount = next_value(&mut it, arg)
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|a| (0.0..=10.0).contains(a))
+                    .unwrap_or_else(|| fail("--sharpen expects an amount from 0 to 10"));
+                options.sharpen = Some(amount);
+                continue;
+            }
+            "--indexed" => {
+                // optional color count; a bare --indexed means 256
+                let colors = match it.peek().and_then(|v| v.parse::<u32>().ok()) {
+                    Some(n) => {
+                        it.next();
+                        n
+                    }
+                    None => 256,
+                };
+                if !(2..=256).contains(&colors) {
+                    fail("--indexed takes a color count from 2 to 256");
+                }
+                options.indexed = Some(colors);
+                continue;
+            }
+            "--blur-region" => {
+                options.blur_regions.push(
+                    parse_rect(next_value(&mut it, arg))
+                        .unwrap_or_else(|| fail("--blur-region expects <x>,<y>,<w>,<h>")),
+                );
+                continue;
+            }
+            "--blur-radius" => {
+                let radius: u32 = parse_num(next_value(&mut it, arg), "blur radius");
+                if radius == 0 {
+                    fail("--blur-radius must be > 0");
+                }
+                options.blur_radius = Some(radius);
+                continue;
+            }
+            "--exclude-window" => {
+                options
+                    .exclude_windows
+                    .push(next_value(&mut it, arg).to_string());
+                continue;
+            }
+            "--thumbnail" => {
+                options.thumbnail = Some(
+                    parse_size(next_value(&mut it, arg))
+                        .unwrap_or_else(|| fail("--thumbnail expects <w>x<h>, e.g. 320x240")),
+                );
+                continue;
+            }
+            "--thumb-format" => {
+                let ext = next_value(&mut it, arg).trim_start_matches('.');
+                options.thumb_format = Some(ext.to_ascii_lowercase());
+                continue;
+            }
+            "--bench" => {
+                bench = true;
+                continue;
+            }
+            "--compare" => {
+                compare = Some(next_value(&mut it, arg));
+                continue;
+            }
+            "--threshold" => {
+                threshold = next_value(&mut it, arg)
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|t| (0.0..=100.0).contains(t))
+                    .unwrap_or_else(|| fail("--threshold expects a percentage from 0 to 100"));
+                continue;
+            }
+            "--diff-out" => {
+                diff_out = Some(next_value(&mut it, arg).to_string());
+                continue;
+            }
+            "--frames" => {
+                frames = parse_num(next_value(&mut it, arg), "frames");
+                if frames == 0 {
+                    fail("--frames must be > 0");
+                }
+                continue;
+            }
+            "--monitor-name" => {
+                monitor_name = Some(next_value(&mut it, arg).to_string());
+                continue;
+            }
+            "--corners" => {
+                let corners = parse_rect(next_value(&mut it, arg))
+                    .unwrap_or_else(|| fail("--corners expects <x1>,<y1>,<x2>,<y2>"));
+                rect =
+                    Some(corners_rect(corners).unwrap_or_else(|| {
+                        fail("--corners must span a positive width and height")
+                    }));
+                continue;
+            }
+            "--around-cursor" => {
+                around = Some(
+                    parse_size(next_value(&mut it, arg))
+                        .unwrap_or_else(|| fail("--around-cursor expects <w>x<h>, e.g. 400x300")),
+                );
+                continue;
+            }
+            "--append-dimensions" => {
+                append_dimensions = true;
+                continue;
+            }
+            "--temp" => {
+                temp = true;
+                continue;
+            }
+            "--dry-run" => {
+                dry_run = true;
+                continue;
+            }
+            "--progress" => {
+                progress = true;
+                continue;
+            }
+            "--base64" => {
+                base64 = true;
+                continue;
+            }
+            "--data-uri" => {
+                data_uri = true;
+                continue;
+            }
+            "--dip" => {
+                dip = true;
+                continue;
+            }
+            "--json" => {
+                json = true;
+                continue;
+            }
+            "--each-monitor" => {
+                each_monitor = true;
+                continue;
+            }
+            "--serial" => {
+                serial = true;
+                continue;
+            }
+            "--stdin-coords" => {
+                stdin_coords = true;
+                continue;
+            }
+            "--client-only" => {
+                client_only = true;
+                continue;
+            }
+            "--dwm-thumbnail" => {
+                dwm_thumbnail = true;
+                continue;
+            }
+            "--rect-from-window-and-monitor" => {
+                on_monitor = Some(next_value(&mut it, arg).to_string());
+                continue;
+            }
+            "--window-region" => {
+                region = Some(
+                    parse_rect(next_value(&mut it, arg))
+                        .unwrap_or_else(|| fail("--window-region expects <x>,<y>,<w>,<h>")),
+                );
+                continue;
+            }
+            "--brightness" => {
+                let v: i32 = parse_num(next_value(&mut it, arg), "brightness");
+                options.brightness = v.clamp(-100, 100);
+                continue;
+            }
+            "--contrast" => {
+                let v: i32 = parse_num(next_value(&mut it, arg), "contrast");
+                options.contrast = v.clamp(-100, 100);
+                continue;
+            }
+            "--bit-depth" => {
+                options.bit_depth = match next_value(&mut it, arg) {
+                    "24" => BitDepth::Bits24,
+                    "32" => BitDepth::Bits32,
+                    _ => fail("--bit-depth expects 24 or 32"),
+                };
+                continue;
+            }
+            "--force-24" => {
+                options.bit_depth = BitDepth::Bits24;
+                continue;
+            }
+            "--force-32" => {
+                options.bit_depth = BitDepth::Bits32;
+                continue;
+            }
+            "--verbose" | "-v" => {
+                options.verbose = true;
+                continue;
+            }
+            "--icc" => {
+                let path = next_value(&mut it, arg);
+                let profile = std::fs::read(path)
+                    .unwrap_or_else(|e| fail(&format!("cannot read ICC profile {path}: {e}")));
+                options.icc_profile = Some(profile);
+                continue;
+            }
+            "--srgb" => {
+                options.icc_profile.get_or_insert_with(srgb_icc_profile);
+                continue;
+            }
+            "--cursor" | "--no-cursor" => {
+                cursor = Some(arg == "--cursor");
+                continue;
+            }
+            "--keep-alpha" => {
+                options.keep_alpha = true;
+                continue;
+            }
+            "--no-atomic" => {
+                options.no_atomic = true;
+                continue;
+            }
+            "--shadow" => {
+                shadow = true;
+                continue;
+            }
+            "--shadow-blur" => {
+                shadow_style.blur = parse_num(next_value(&mut it, arg), "shadow-blur");
+                shadow_tuned = true;
+                continue;
+            }
+            "--shadow-offset" => {
+                shadow_style.offset = parse_point(next_value(&mut it, arg))
+                    .flatten()
+                    .unwrap_or_else(|| fail("--shadow-offset expects <x>,<y>"));
+                let (x, y) = shadow_style.offset;
+                let range = -MAX_SHADOW_OFFSET..=MAX_SHADOW_OFFSET;
+                if !range.contains(&x) || !range.contains(&y) {
+                    fail("--shadow-offset must be within -4096..4096 on each axis");
+                }
+                shadow_tuned = true;
+                continue;
+            }
+            "--shadow-color" => {
+                shadow_style.color = parse_shadow_color(next_value(&mut it, arg))
+                    .unwrap_or_else(|| fail("--shadow-color expects #RRGGBB or #RRGGBBAA"));
+                shadow_tuned = true;
+                continue;
+            }
+            "--transparent-color" => {
+                options.transparent_color = Some(
+                    parse_color(next_value(&mut it, arg))
+                        .unwrap_or_else(|| fail("--transparent-color expects #RRGGBB")),
+                );
+                continue;
+            }
+            "--tolerance" => {
+                let t: f64 = next_value(&mut it, arg)
+                    .parse()
+                    .unwrap_or_else(|_| fail("--tolerance expects a number"));
+                if t.is_nan() || t < 0.0 {
+                    fail("--tolerance must be >= 0");
+                }
+                options.color_tolerance = t;
+                continue;
+            }
+            "--quiet" | "-q" => {
+                options.quiet = true;
+                continue;
+            }
+            "--force-captureblt" => {
+                options.force_captureblt = true;
+                continue;
+            }
+            "--rop" => {
+                options.raster_op =
+                    Some(parse_rop(next_value(&mut it, arg)).unwrap_or_else(|| {
+                        fail("--rop expects a ROP name (NOTSRCCOPY, ...) or hex code (0x00330008)")
+                    }));
+                continue;
+            }
+            "--format" => {
+                let ext = next_value(&mut it, arg).trim_start_matches('.');
+                options.format = Some(ext.to_ascii_lowercase());
+                continue;
+            }
+            "--quality" => {
+                let quality: u32 = parse_num(next_value(&mut it, arg), "quality");
+                if quality > 100 {
+                    fail("--quality must be 0..100");
+                }
+                options.quality = Some(quality);
+                continue;
+            }
+            "--png-compression" => {
+                let level: u32 = parse_num(next_value(&mut it, arg), "png-compression");
+                if level > 9 {
+                    fail("--png-compression must be 0..9");
+                }
+                options.png_compression = Some(level);
+                continue;
+            }
+            "--no-gdiplus" => {
+                options.no_gdiplus = true;
+                continue;
+            }
+            "--label" => {
+                options.label = Some(next_value(&mut it, arg).to_string());
+                continue;
+            }
+            "--label-pos" => {
+                options.label_pos = match next_value(&mut it, arg) {
+                    "tl" => LabelPos::TopLeft,
+                    "tr" => LabelPos::TopRight,
+                    "bl" => LabelPos::BottomLeft,
+                    "br" => LabelPos::BottomRight,
+                    _ => fail("--label-pos expects tl, tr, bl or br"),
+                };
+                continue;
+            }
+            flag if flag.starts_with("--") => {
+                usage();
+                std::process::exit(EXIT_USAGE);
+            }
+            _ => {
+                positional.push(arg);
+                continue;
+            }
+        };
+        if mode.replace(new_mode).is_some() {
+            fail("only one capture mode may be given");
         }
     }
-    if num == 0 || size == 0 {
-        return Err(Error::new(HRESULT(E_FAIL.0), "No image encoders available"));
+    // Positionals:
+    // x y w h filename => explicit rectangle
+    // filename         => flag-selected mode, default --primary
+    if let Some(file) = gif {
+        if interval.is_none() || count.is_none() {
+            fail("--gif needs --interval and --count");
+        }
+        positional.push(file); // the GIF is the output file
+    }
+    if temp {
+        if !matches!(positional.len(), 0 | 4) {
+            fail("--temp picks the output file; don't give a filename");
+        }
+        if stdin_coords || interval.is_some() || bench || compare.is_some() || each_monitor {
+            fail("--temp applies to single captures only");
+        }
+        if append_dimensions {
+            fail("--temp picks the whole name; drop --append-dimensions");
+        }
+        positional.push(""); // replaced by a fresh temp file before capturing
     }
-    // aligned allocation
-    let encoders_ptr = unsafe { CoTaskMemAlloc(size as usize) } as *mut GdiPlus::ImageCodecInfo;
-    if encoders_ptr.is_null() {
-        return Err(Error::new(HRESULT(E_FAIL.0), "CoTaskMemAlloc failed"));
+    if data_uri && !base64 {
+        fail("--data-uri needs --base64");
     }
-    // ensure free on all paths
-    let _encoders_guard = EncodersGuard(encoders_ptr as *mut c_void);
-    unsafe {
-        if GdiPlus::GdipGetImageEncoders(num, size, encoders_ptr) != GdiPlus::Ok {
-            return Err(Error::new(HRESULT(E_FAIL.0), "GdipGetImageEncoders failed"));
+    if base64 {
+        if !matches!(positional.len(), 0 | 4) {
+            fail("--base64 writes to stdout; don't give a filename");
         }
+        if stdin_coords || interval.is_some() || bench || compare.is_some() || each_monitor {
+            fail("--base64 applies to single captures only");
+        }
+        if temp || json || append_dimensions || options.thumbnail.is_some() {
+            fail(
+                "--base64 writes no file; drop --temp, --json, --append-dimensions and --thumbnail",
+            );
+        }
+        if options.no_gdiplus {
+            fail("--base64 needs a GDI+ encoder and cannot be combined with --no-gdiplus");
+        }
+        positional.push(""); // nothing is written; the format comes from --format
     }
-    // normalize the requested extension (".png", ".jpg", ...)
-    let want = format!(".{}", ext.trim_start_matches('.')).to_ascii_lowercase();
-    // iterate the array portion at the beginning of the allocation. Each struct's pointer
-    // fields point into the same allocated block, so 'encoders_ptr' must stay alive until
-    // we finish.
-    for i in 0..(num as usize) {
-        let info = unsafe { &*encoders_ptr.add(i) };
-        // some codecs may not provide FilenameExtension.
-        if info.FilenameExtension.is_null() {
-            continue;
+    if bench && matches!(positional.len(), 0 | 4) {
+        positional.push("bench.png"); // no output file: time the PNG encoder
+    }
+    if let Some(reference) = compare {
+        if interval.is_some() || bench {
+            fail("--compare cannot be combined with --interval or --bench");
         }
-        // read the UTF-16 NUL-terminated string.
-        let p = PCWSTR::from_raw(info.FilenameExtension.0);
-        let exts = unsafe { p.to_string()? };
-        // patterns look like "*.JPG;*.JPEG;*.JPE;*.JFIF".
-        for pat in exts.split(';') {
-            let pat = pat.trim().trim_start_matches('*').to_ascii_lowercase(); // ".jpg"
-            if pat == want {
-                return Ok(info.Clsid);
-            }
-        }
-    }
-    Err(Error::new(
-        HRESULT(E_FAIL.0),
-        "No encoder found for the given extension",
-    ))
-}
-
-fn gdip_startup() -> windows::core::Result<usize> {
-    unsafe {
-        let mut input: GdiPlus::GdiplusStartupInput = zeroed();
-        input.GdiplusVersion = 1;
-        let mut token: usize = 0;
-        if GdiPlus::GdiplusStartup(
-            &mut token,
-            &input,
-            null_mut::<GdiPlus::GdiplusStartupOutput>(),
-        ) != GdiPlus::Ok
+        positional.push(reference); // read, never written
+    } else if threshold != 0.0 || diff_out.is_some() {
+        fail("--threshold and --diff-out need --compare");
+    }
+    if options.no_gdiplus && !options.exclude_windows.is_empty() {
+        // refuse rather than silently write the windows that were meant to be hidden
+        fail("--exclude-window needs GDI+ and cannot be combined with --no-gdiplus");
+    }
+    if options.blur_radius.is_some() && options.blur_regions.is_empty() {
+        fail("--blur-radius needs --blur-region");
+    }
+    if options.thumb_format.is_some() && options.thumbnail.is_none() {
+        fail("--thumb-format needs --thumbnail");
+    }
+    if wait_timeout.is_some() && !wait_for_window {
+        fail("--wait-timeout needs --wait-for-window");
+    }
+    if options.quiet && options.verbose {
+        fail("--quiet and --verbose cannot be combined");
+    }
+    if count.is_some() && interval.is_none() {
+        fail("--count needs --interval");
+    }
+    if progress && interval.is_none() {
+        fail("--progress needs --interval");
+    }
+    if (client_only || region.is_some() || padding != 0 || dwm_thumbnail) && window.is_none() {
+        fail(
+            "--client-only, --window-region, --padding and --dwm-thumbnail need --window, --hwnd \
+             or --foreground",
+        );
+    }
+    if on_monitor.is_some() && window.is_none() {
+        fail("--rect-from-window-and-monitor needs --window, --hwnd or --foreground");
+    }
+    if dwm_thumbnail && padding != 0 {
+        fail("--dwm-thumbnail renders the window alone; drop --padding");
+    }
+    if json && (stdin_coords || interval.is_some() || bench || compare.is_some() || each_monitor) {
+        fail("--json reports single captures only");
+    }
+    if dry_run && stdin_coords {
+        fail("--dry-run cannot plan --stdin-coords; its rectangles arrive at run time");
+    }
+    if serial && !each_monitor {
+        fail("--serial needs --each-monitor");
+    }
+    if each_monitor && (interval.is_some() || bench || compare.is_some()) {
+        fail("--each-monitor cannot be combined with --interval, --bench or --compare");
+    }
+    if [
+        window.is_some(),
+        mode.is_some(),
+        around.is_some(),
+        rect.is_some(),
+        monitor_name.is_some(),
+        each_monitor,
+    ]
+    .iter()
+    .filter(|&&m| m)
+    .count()
+        > 1
+    {
+        fail("only one capture mode may be given");
+    }
+    let target = match (positional.len(), mode) {
+        (0, None) if stdin_coords && window.is_none() => Target::StdinCoords,
+        _ if stdin_coords => fail("--stdin-coords takes no capture mode or filename"),
+        (1, None) if window.is_some() => Target::Window {
+            selector: window.unwrap(),
+            client_only,
+            region,
+            padding,
+            wait: wait_for_window.then(|| wait_timeout.unwrap_or(Duration::from_secs(30))),
+            dwm_thumbnail,
+            monitor: on_monitor,
+        },
+        (5, None)
+            if window.is_none() && around.is_none() && rect.is_none() && monitor_name.is_none() =>
         {
-            return Err(Error::new(HRESULT(E_FAIL.0), "GdiplusStartup failed"));
-        }
-        Ok(token)
-    }
-}
-
-fn gdip_shutdown(token: usize) {
-    unsafe { GdiPlus::GdiplusShutdown(token) };
-}
-
-fn make_dib_section(
-    w: i32,
-    h: i32,
-    hdc_palette: Gdi::HDC,
-) -> windows::core::Result<(Gdi::HBITMAP, *mut u8)> {
-    // 32bpp, bottom-up bitmap (positive height)
-    let mut bmi: Gdi::BITMAPINFO = unsafe { zeroed() };
-    bmi.bmiHeader.biSize = size_of::<Gdi::BITMAPINFOHEADER>() as u32;
-    bmi.bmiHeader.biWidth = w;
-    bmi.bmiHeader.biHeight = h; // positive => bottom-up
-    bmi.bmiHeader.biPlanes = 1;
-    bmi.bmiHeader.biBitCount = 32;
-    bmi.bmiHeader.biCompression = Gdi::BI_RGB.0;
-    let mut bits: *mut core::ffi::c_void = null_mut();
-    // unwrap the Result<HBITMAP> here
-    let hbmp: Gdi::HBITMAP = unsafe {
-        Gdi::CreateDIBSection(
-            Some(hdc_palette),
-            &bmi,
-            Gdi::DIB_RGB_COLORS,
-            &mut bits,
-            None, // no file mapping
-            0,
-        )?
+            let x = parse_num(positional[0], "x");
+            let y = parse_num(positional[1], "y");
+            let w = parse_num(positional[2], "width");
+            let h = parse_num(positional[3], "height");
+            if w <= 0 || h <= 0 {
+                fail("width and height must be > 0");
+            }
+            Target::Rect(x, y, w, h)
+        }
+        (1, None) if rect.is_some() => {
+            let (x, y, w, h) = rect.unwrap();
+            Target::Rect(x, y, w, h)
+        }
+        (1, None) if monitor_name.is_some() => Target::MonitorName(monitor_name.unwrap()),
+        (1, None) if each_monitor => Target::EachMonitor { serial },
+        (1, None) if around.is_some() => {
+            let (w, h) = around.unwrap();
+            Target::AroundCursor(w, h)
+        }
+        (1, mode) if around.is_none() && rect.is_none() && monitor_name.is_none() => {
+            Target::Screen(mode.unwrap_or(ScreenMode::Primary))
+        }
+        _ => {
+            usage();
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+    if shadow_tuned && !shadow {
+        fail("--shadow-blur, --shadow-offset and --shadow-color need --shadow");
+    }
+    if shadow {
+        if !matches!(target, Target::Window { .. }) {
+            fail("--shadow applies to window captures (--window, --hwnd or --foreground)");
+        }
+        options.shadow = Some(shadow_style);
+    }
+    if dip && !matches!(target, Target::Rect(..)) {
+        fail("--dip applies to an explicit rectangle (x y w h or --corners)");
+    }
+    // an explicit --cursor/--no-cursor wins; otherwise the pointer is wanted on whole-screen
+    // captures and is noise on window, client-area and explicit-rectangle captures
+    options.include_cursor = cursor.unwrap_or(matches!(
+        target,
+        Target::Screen(_) | Target::MonitorName(_) | Target::EachMonitor { .. }
+    ));
+    Cli {
+        target,
+        filename: positional.last().map(|s| s.to_string()).unwrap_or_default(),
+        options,
+        interval,
+        count,
+        gif: gif.is_some(),
+        bench: bench.then_some(frames),
+        compare: compare.map(|_| Compare {
+            threshold,
+            diff_out,
+        }),
+        dip,
+        json,
+        temp,
+        append_dimensions,
+        dry_run,
+        base64,
+        data_uri,
+        progress,
+    }
+}
+
+// parse one "x y w h filename" worker line; the filename may contain spaces.
+fn parse_coords_line(line: &str) -> Result<(Rect, &str), String> {
+    let mut parts = line.trim().splitn(5, char::is_whitespace);
+    let mut num = |what: &str| -> Result<i32, String> {
+        parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(|| format!("{what} must be an integer"))
     };
-    Ok((hbmp, bits as *mut u8))
+    let rect = (num("x")?, num("y")?, num("width")?, num("height")?);
+    if rect.2 <= 0 || rect.3 <= 0 {
+        return Err("width and height must be > 0".into());
+    }
+    match parts.next().map(str::trim) {
+        Some(f) if !f.is_empty() => Ok((rect, f)),
+        _ => Err("missing filename".into()),
+    }
 }
 
-fn capture_region(x: i32, y: i32, w: i32, h: i32) -> windows::core::Result<Gdi::HBITMAP> {
-    let raster_op: ROP_CODE = SRCCOPY | CAPTUREBLT;
-    unsafe {
-        let hdc_screen = Gdi::GetDC(None);
-        if hdc_screen.0.is_null() {
-            return Err(Error::new(HRESULT(E_FAIL.0), "GetDC failed"));
+// long-lived worker: one GDI+ session, one capture per stdin line until EOF.
+fn run_stdin_coords(options: &Options) -> Result<()> {
+    let session = Session::new()?;
+    handle_ctrl_c();
+    let mut out = std::io::stdout().lock();
+    let mut input = std::io::stdin().lock();
+    let mut buf = Vec::new();
+    let reader = unsafe { OpenThread(THREAD_TERMINATE, false, GetCurrentThreadId()) }?;
+    let _reader_guard = ReaderGuard(reader);
+    STDIN_READER.store(reader.0, Ordering::SeqCst);
+    loop {
+        WAITING_FOR_INPUT.store(true, Ordering::SeqCst);
+        buf.clear();
+        // a Ctrl+C that came before the flag was set won't cancel the read
+        let read = if interrupted() {
+            Ok(0)
+        } else {
+            input.read_until(b'\n', &mut buf)
+        };
+        WAITING_FOR_INPUT.store(false, Ordering::SeqCst);
+        match read {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(_) if interrupted() => break,
+            Err(e) => return Err(e.into()),
         }
-        let _screen_guard = ScreenDcGuard(hdc_screen);
-
-        let mem_dc = Gdi::CreateCompatibleDC(Some(hdc_screen));
-        if mem_dc.0.is_null() {
-            return Err(Error::new(HRESULT(E_FAIL.0), "CreateCompatibleDC failed"));
+        // e.g. a filename from an ANSI code page: report it and keep serving
+        let Ok(line) = std::str::from_utf8(&buf) else {
+            let _ = writeln!(out, "ERR line is not valid UTF-8");
+            let _ = out.flush();
+            continue;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = parse_coords_line(line).and_then(|(rect, filename)| {
+            let (x, y, w, h) = screen_rect(ScreenMode::Virtual)
+                .and_then(|desktop| clamp_to_desktop(rect, desktop))
+                .map_err(|e| e.to_string())?;
+            capture_request(options, (x, y, w, h), Some(filename))
+                .and_then(|request| session.capture_request(&request))
+                .map(|_| filename)
+                .map_err(|e| e.to_string())
+        });
+        let _ = match result {
+            Ok(filename) => writeln!(out, "OK {filename}"),
+            Err(reason) => writeln!(out, "ERR {reason}"),
+        };
+        let _ = out.flush();
+        if interrupted() {
+            break;
         }
-        let _mem_guard = DcGuard(mem_dc);
+    }
+    Ok(())
+}
+
+// unregisters and closes the worker thread handle the Ctrl+C handler cancels reads on.
+struct ReaderGuard(HANDLE);
+impl Drop for ReaderGuard {
+    fn drop(&mut self) {
+        STDIN_READER.store(null_mut(), Ordering::SeqCst);
+        let _ = unsafe { CloseHandle(self.0) };
+    }
+}
 
-        // create target bitmap (deleted automatically unless we forget it)
-        let (hbmp, _bits) = make_dib_section(w, h, hdc_screen)?;
-        let hbmp_guard = BitmapGuard(hbmp);
+// "2" => the second monitor in --each-monitor order (left to right), anything else => a device
+// name as for --monitor-name.
+fn monitor_by_spec(spec: &str) -> Result<Rect> {
+    let Ok(n) = spec.parse::<usize>() else {
+        return monitor_by_name(spec);
+    };
+    let monitors = monitor_rects()?;
+    n.checked_sub(1)
+        .and_then(|i| monitors.get(i).copied())
+        .ok_or_else(|| {
+            SnapshotError::InvalidRegion(format!(
+                "no monitor {n}; there are {} (numbered from 1, left to right)",
+                monitors.len()
+            ))
+        })
+}
 
-        // select it into mem DC; selection restored automatically
-        let old = Gdi::SelectObject(mem_dc, hbmp.into());
-        if old.is_invalid() {
-            return Err(Error::new(HRESULT(E_FAIL.0), "SelectObject failed"));
+// resolve the capture target to a screen rectangle, and for a window target the window it
+// was measured from, so the capture uses that same window.
+fn resolve_target(cli: &Cli) -> Result<(Rect, Option<HWND>)> {
+    let options = &cli.options;
+    Ok(match cli.target {
+        Target::StdinCoords => unreachable!("stdin worker resolves rectangles per line"),
+        Target::EachMonitor { .. } => unreachable!("each monitor is captured separately"),
+        Target::Rect(x, y, w, h) => {
+            let (x, y, w, h) = if cli.dip {
+                let (scale, (mx, my, _, _)) = monitor_scale((x, y, w, h))?;
+                scale_rect((x, y, w, h), (mx, my), scale, 100)
+            } else {
+                (x, y, w, h)
+            };
+            // clip to the virtual desktop so off-screen parts don't come back black
+            let desktop = screen_rect(ScreenMode::Virtual)?;
+            let clipped = clamp_to_desktop((x, y, w, h), desktop)?;
+            if options.verbose {
+                let (dx, dy, dw, dh) = desktop;
+                eprintln!(
+                    "virtual desktop: {dx},{dy} {dw}x{dh} (negative = left of/above primary)"
+                );
+                eprintln!("requested: {x},{y} {w}x{h}");
+                if clipped != (x, y, w, h) {
+                    let (cx, cy, cw, ch) = clipped;
+                    eprintln!("clipped to desktop: {cx},{cy} {cw}x{ch}");
+                }
+            }
+            (clipped, None)
+        }
+        Target::MonitorName(ref name) => (monitor_by_name(name)?, None),
+        Target::AroundCursor(w, h) => {
+            let (cx, cy) = cursor_pos()?;
+            let desktop = screen_rect(ScreenMode::Virtual)?;
+            // slide rather than clip so the box keeps its requested size near screen edges
+            let origin = cx.checked_sub(w / 2).zip(cy.checked_sub(h / 2));
+            let (x, y) = origin.ok_or_else(|| {
+                SnapshotError::InvalidRegion(format!("--around-cursor {w}x{h} is too large"))
+            })?;
+            let rect = slide_into((x, y, w, h), desktop)?;
+            if options.verbose {
+                let (x, y, w, h) = rect;
+                eprintln!("cursor at {cx},{cy}; capturing {x},{y} {w}x{h}");
+            }
+            (rect, None)
         }
-        let _sel_guard = SelectGuard { dc: mem_dc, old };
+        Target::Screen(mode) => {
+            let (x, y, w, h) = screen_rect(mode)?;
+            if w <= 0 || h <= 0 {
+                return Err(SnapshotError::InvalidRegion(format!(
+                    "detected non-positive screen size: {w}x{h}"
+                )));
+            }
+            ((x, y, w, h), None)
+        }
+        Target::Window {
+            ref selector,
+            client_only,
+            region,
+            padding,
+            wait,
+            ref monitor,
+            ..
+        } => {
+            let hwnd = match (selector, wait) {
+                (WindowSelector::Title(title), Some(timeout)) => wait_for_window(title, timeout)?,
+                _ => find_window(selector)?,
+            };
+            let rect = window_rect(hwnd, client_only)?;
+            let rect = match region {
+                Some(r) => sub_rect(rect, r)?,
+                None => rect,
+            };
+            let rect = if padding == 0 {
+                rect
+            } else {
+                // extra desktop context around the window, kept on-screen
+                let padded = pad_rect(rect, padding)?;
+                clamp_to_desktop(padded, screen_rect(ScreenMode::Virtual)?)?
+            };
+            let rect = match monitor {
+                Some(spec) => window_on_monitor(rect, monitor_by_spec(spec)?)?,
+                None => rect,
+            };
+            (rect, Some(hwnd))
+        }
+    })
+}
 
-        // BitBlt from screen into our DIB
-        Gdi::BitBlt(mem_dc, 0, 0, w, h, Some(hdc_screen), x, y, raster_op)?;
+// capture into a new uniquely named file in %TEMP% and print its absolute path. the file is
+// created with create_new first, so concurrent runs can never pick the same name.
+fn capture_to_temp(cli: &mut Cli, (x, y, w, h): Rect, window: Option<HWND>) -> Result<()> {
+    let ext = cli.options.format.clone().unwrap_or_else(|| "png".into());
+    let dir = env::temp_dir();
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros());
+    let mut n = 0u32;
+    let path = loop {
+        let path = dir.join(format!(
+            "gdip_snapshot_{}_{stamp}_{n}.{ext}",
+            std::process::id()
+        ));
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => break path,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    cli.filename = path.to_string_lossy().into_owned();
+    if let Err(e) = capture_single(cli, (x, y, w, h), window) {
+        let _ = std::fs::remove_file(&path); // nothing useful to hand to the caller
+        return Err(e);
+    }
+    if cli.options.verbose || cli.json {
+        report_dimensions(cli, (x, y, w, h))?;
+    }
+    if !cli.json {
+        println!("{}", cli.filename); // already in the JSON otherwise
+    }
+    Ok(())
+}
 
-        // success: transfer ownership to caller (prevent guard from deleting it)
-        std::mem::forget(hbmp_guard);
-        Ok(hbmp)
+// physical pixels actually captured vs. the logical (DIP) rectangle apps would report.
+fn report_dimensions(cli: &Cli, physical: Rect) -> Result<()> {
+    let (scale, (mx, my, _, _)) = monitor_scale(physical)?;
+    let logical = match cli.target {
+        Target::Rect(x, y, w, h) if cli.dip => (x, y, w, h), // as requested
+        _ => scale_rect(physical, (mx, my), 100, scale),
+    };
+    if cli.options.verbose {
+        let (x, y, w, h) = physical;
+        let (lx, ly, lw, lh) = logical;
+        eprintln!("physical: {x},{y} {w}x{h}; logical: {lx},{ly} {lw}x{lh} at {scale}% scale");
     }
+    if cli.json {
+        let rect = |(x, y, w, h): Rect| format!(r#"{{"x":{x},"y":{y},"w":{w},"h":{h}}}"#);
+        println!(
+            r#"{{"file":{},"scale_percent":{scale},"physical":{},"logical":{}}}"#,
+            json_string(&cli.filename),
+            rect(physical),
+            rect(logical)
+        );
+    }
+    Ok(())
 }
 
-// wrap HBITMAP -> GDI+ Bitmap, choose encoder by extension, save
-fn save_hbitmap_with_gdiplus(hbmp: Gdi::HBITMAP, filename: &str) -> windows::core::Result<()> {
-    let mut bmp: *mut GdiPlus::GpBitmap = null_mut();
-    unsafe {
-        if GdiPlus::GdipCreateBitmapFromHBITMAP(hbmp, Gdi::HPALETTE(std::ptr::null_mut()), &mut bmp)
-            != GdiPlus::Ok
-        {
-            return Err(Error::new(
-                HRESULT(E_FAIL.0),
-                "GdipCreateBitmapFromHBITMAP failed",
-            ));
+// minimal JSON string literal; filenames are the only free text we emit.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
         }
     }
-    // ensure dispose on all paths
-    let _guard = ImgGuard(bmp as *mut GdiPlus::GpImage);
-    // Pick encoder by extension.
-    let ext = std::path::Path::new(filename)
-        .extension()
-        .and_then(|e| e.to_str())
-        .ok_or_else(|| Error::new(HRESULT(E_INVALIDARG.0), "filename has no extension"))?;
-    let clsid = clsid_for_extension(ext)?;
-    //save output file
-    let wname = wide(filename);
-    unsafe {
-        if GdiPlus::GdipSaveImageToFile(
-            bmp as *mut GdiPlus::GpImage,
-            PCWSTR(wname.as_ptr()),
-            &clsid,
-            null(),
-        ) != GdiPlus::Ok
-        {
-            return Err(Error::new(HRESULT(E_FAIL.0), "GdipSaveImageToFile failed"));
+    out.push('"');
+    out
+}
+
+// "shot.png" + "0001" -> "shot_0001.png"
+fn suffixed_filename(filename: &str, suffix: &str) -> String {
+    let path = Path::new(filename);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{stem}_{suffix}.{ext}"),
+        None => format!("{stem}_{suffix}"),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// "shot.png" -> "shot_0001.png" for interval frames.
+fn numbered_filename(filename: &str, n: u32) -> String {
+    suffixed_filename(filename, &format!("{n:04}"))
+}
+
+// the output filename for a capture of `rect`: with --append-dimensions, "shot.png" ->
+// "shot_1920x1080.png". Frame and monitor numbers are appended after this.
+fn sized_filename(cli: &Cli, (_, _, w, h): Rect) -> String {
+    if cli.append_dimensions && !is_pipe_path(&cli.filename) {
+        suffixed_filename(&cli.filename, &format!("{w}x{h}"))
+    } else {
+        cli.filename.clone()
+    }
+}
+
+// one (rectangle, output file) per monitor, numbered left to right.
+fn monitor_jobs(cli: &Cli) -> Result<Vec<(Rect, String)>> {
+    Ok(monitor_rects()?
+        .into_iter()
+        .enumerate()
+        .map(|(i, rect)| {
+            (
+                rect,
+                numbered_filename(&sized_filename(cli, rect), i as u32 + 1),
+            )
+        })
+        .collect())
+}
+
+// one numbered file per monitor, captured in parallel unless --serial.
+fn run_each_monitor(cli: &Cli, serial: bool) -> Result<()> {
+    let jobs = monitor_jobs(cli)?;
+    let requests = jobs
+        .iter()
+        .map(|(rect, file)| capture_request(&cli.options, *rect, Some(file)))
+        .collect::<Result<Vec<_>>>()?;
+    let start = Instant::now();
+    let results = capture_monitors(&requests, !serial);
+    if cli.options.verbose {
+        let how = if serial { "serially" } else { "in parallel" };
+        eprintln!(
+            "captured {} monitors {how} in {:.1} ms",
+            jobs.len(),
+            start.elapsed().as_secs_f64() * 1000.0
+        );
+    }
+    let mut first_err = None;
+    for (((x, y, w, h), file), result) in jobs.iter().zip(results) {
+        match result {
+            Ok(_) => println!("{file}: {x},{y} {w}x{h}"),
+            Err(e) => {
+                eprintln!("{file}: {e}");
+                first_err.get_or_insert(e);
+            }
         }
     }
+    first_err.map_or(Ok(()), Err)
+}
+
+// the session captures go through: with --dwm-thumbnail, the DWM thumbnail of `window`, the
+// one resolve_target measured; else the screen.
+fn open_session(cli: &Cli, window: Option<HWND>) -> Result<Session> {
+    match (&cli.target, window) {
+        (
+            Target::Window {
+                client_only,
+                dwm_thumbnail: true,
+                ..
+            },
+            Some(hwnd),
+        ) => Session::with_dwm_thumbnail(hwnd, *client_only),
+        _ => Session::new(),
+    }
+}
+
+// a capture of the resolved rectangle with the command line's options, saved to `file` or,
+// without one, encoded in memory.
+fn capture_request(
+    options: &Options,
+    (x, y, w, h): Rect,
+    file: Option<&str>,
+) -> Result<CaptureRequest> {
+    let builder = CaptureOptions::from_options(options.clone()).rect(x, y, w, h);
+    match file {
+        Some(file) => builder.file(file),
+        None => builder,
+    }
+    .build()
+}
+
+// a one-off capture of `rect` to cli.filename, warning (unless --quiet) when it came back
+// entirely black.
+fn capture_single(cli: &Cli, rect: Rect, window: Option<HWND>) -> Result<CaptureResult> {
+    let request = CaptureOptions::from_options(cli.options.clone())
+        .rect(rect.0, rect.1, rect.2, rect.3)
+        .file(&cli.filename)
+        .check_black(!cli.options.quiet)
+        .build()?;
+    let result = match cli.target {
+        Target::Window {
+            dwm_thumbnail: true,
+            ..
+        } => open_session(cli, window)?.capture_request(&request)?,
+        _ => capture(&request)?,
+    };
+    if result.all_black {
+        warn_all_black(&cli.options, rect);
+    }
+    Ok(result)
+}
+
+// the likely causes of an all-black capture; a remote session that used CAPTUREBLT is the
+// one case with a flag to fix it.
+fn warn_all_black(options: &Options, (_, _, w, h): Rect) {
+    const CAPTUREBLT: u32 = 0x4000_0000;
+    eprintln!("warning: the captured {w}x{h} area is entirely black");
+    let captureblt = options
+        .raster_op
+        .map_or(options.force_captureblt, |rop| rop & CAPTUREBLT != 0);
+    if is_remote_session() && captureblt {
+        eprintln!(
+            "         this is a remote session and CAPTUREBLT was used; try without \
+             --force-captureblt or --rop CAPTUREBLT"
+        );
+    } else {
+        eprintln!(
+            "         (a locked or secure desktop, a screen saver, or a window blocking \
+             capture gives black; --dwm-thumbnail can help for some windows)"
+        );
+    }
+}
+
+// encode in memory and print the image as one line of base64 (or a data: URI) on stdout.
+fn capture_to_base64(cli: &Cli, rect: Rect, window: Option<HWND>) -> Result<()> {
+    let request = capture_request(&cli.options, rect, None)?;
+    let encoded = open_session(cli, window)?
+        .capture_request(&request)?
+        .image
+        .expect("a request without a file is encoded in memory");
+    if cli.options.verbose {
+        let (_, _, w, h) = rect;
+        eprintln!(
+            "{w}x{h} {}, {} bytes",
+            encoded.mime_type,
+            encoded.bytes.len()
+        );
+    }
+    if cli.data_uri {
+        println!("{}", encoded.data_uri());
+    } else {
+        println!("{}", encoded.base64());
+    }
     Ok(())
 }
 
-fn usage() {
-    eprintln!("Usage:");
-    eprintln!("  gdip_snapshot <x> <y> <width> <height> <output_file>");
-    eprintln!("  gdip_snapshot --full <output_file>     # all monitors (virtual desktop)");
-    eprintln!("  gdip_snapshot --primary <output_file>  # primary monitor only");
-    eprintln!("  gdip_snapshot <output_file>            # default: --primary");
+// "m:ss", or "h:mm:ss" from an hour on.
+fn clock(d: Duration) -> String {
+    let s = d.as_secs();
+    match s / 3600 {
+        0 => format!("{}:{:02}", s / 60, s % 60),
+        h => format!("{h}:{:02}:{:02}", s / 60 % 60, s % 60),
+    }
+}
+
+// --progress: one status line on stderr, rewritten in place when stderr is a terminal and
+// printed once per frame otherwise (logs, CI).
+struct Progress {
+    start: Instant,
+    interval: Duration,
+    total: Option<u32>,
+    tty: bool,
 }
 
-/// Returns (x, y, w, h) for the chosen screen mode.
-fn screen_rect(mode: ScreenMode) -> (i32, i32, i32, i32) {
-    match mode {
-        ScreenMode::Virtual => {
-            // entire virtual desktop (spans all monitors; x/y can be negative)
-            let x = unsafe { GetSystemMetrics(SM_XVIRTUALSCREEN) };
-            let y = unsafe { GetSystemMetrics(SM_YVIRTUALSCREEN) };
-            let w = unsafe { GetSystemMetrics(SM_CXVIRTUALSCREEN) };
-            let h = unsafe { GetSystemMetrics(SM_CYVIRTUALSCREEN) };
-            (x, y, w, h)
+impl Progress {
+    fn new(interval: Duration, total: Option<u32>) -> Self {
+        Self {
+            start: Instant::now(),
+            interval,
+            total,
+            tty: std::io::stderr().is_terminal(),
         }
-        ScreenMode::Primary => {
-            // primary monitor only (origin at 0,0)
-            let w = unsafe { GetSystemMetrics(SM_CXSCREEN) };
-            let h = unsafe { GetSystemMetrics(SM_CYSCREEN) };
-            (0, 0, w, h)
+    }
+
+    fn update(&self, frames: u32) {
+        let elapsed = clock(self.start.elapsed());
+        let line = match self.total {
+            // frames are paced by the interval, so that is what the rest will take
+            Some(total) => {
+                let left = self.interval * total.saturating_sub(frames);
+                format!(
+                    "{frames}/{total} frames, {elapsed} elapsed, ~{} left",
+                    clock(left)
+                )
+            }
+            None => format!("{frames} frames, {elapsed} elapsed"),
+        };
+        if self.tty {
+            eprint!("\r{line}\x1b[K");
+        } else {
+            eprintln!("{line}");
         }
     }
+
+    fn finish(&self) {
+        if self.tty {
+            eprintln!();
+        }
+    }
+}
+
+// repeated captures: numbered files, or frames of one animated GIF with --gif.
+fn run_interval(cli: &Cli, interval: Duration) -> Result<()> {
+    let (mut rect, window) = resolve_target(cli)?;
+    let session = open_session(cli, window)?;
+    let mut gif = match (cli.gif, cli.count) {
+        (true, Some(count)) => Some(session.gif(&sized_filename(cli, rect), interval, count)?),
+        _ => None,
+    };
+    let progress = cli.progress.then(|| Progress::new(interval, cli.count));
+    handle_ctrl_c();
+    let mut n = 0;
+    while cli.count.is_none_or(|count| n < count) && !interrupted() {
+        let started = Instant::now();
+        n += 1;
+        // monitors can be plugged in or change resolution mid-run; follow them. an explicit
+        // rectangle keeps its size
+        if n > 1 && matches!(cli.target, Target::Screen(_) | Target::MonitorName(_)) {
+            let (now, _) = resolve_target(cli)?;
+            if now != rect && cli.options.verbose {
+                let ((ox, oy, ow, oh), (x, y, w, h)) = (rect, now);
+                eprintln!("screen changed: {ox},{oy} {ow}x{oh} -> {x},{y} {w}x{h}");
+            }
+            rect = now;
+        }
+        let (x, y, w, h) = rect;
+        match gif.as_mut() {
+            Some(gif) => gif.add_frame(x, y, w, h, &cli.options)?,
+            None => {
+                // a pipe gets every frame; its reader tells them apart
+                let filename = if is_pipe_path(&cli.filename) {
+                    cli.filename.clone()
+                } else {
+                    numbered_filename(&sized_filename(cli, rect), n)
+                };
+                session.capture_request(&capture_request(&cli.options, rect, Some(&filename))?)?;
+            }
+        }
+        if let Some(progress) = &progress {
+            progress.update(n);
+        }
+        if cli.count.is_none_or(|count| n < count) {
+            sleep_unless_interrupted(interval.saturating_sub(started.elapsed()));
+        }
+    }
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    match gif {
+        Some(gif) => gif.finish(),
+        None => Ok(()),
+    }
 }
 
-#[derive(Clone, Copy)]
-enum ScreenMode {
-    Virtual,
-    Primary,
+fn print_phase(name: &str, p: &PhaseStats) {
+    println!(
+        "{name:<8} min {:8.2} ms  median {:8.2} ms  max {:8.2} ms  avg {:8.2} ms  {:8.1} fps",
+        p.min_ms, p.median_ms, p.max_ms, p.avg_ms, p.fps
+    );
 }
 
-fn capture_rectangle(x: i32, y: i32, w: i32, h: i32, filename: &str) -> windows::core::Result<()> {
-    let _gdip = GdiplusGuard::new()?; // starts and shuts down GDI+ automatically
-    let hbmp = capture_region(x, y, w, h)?;
-    let result = save_hbitmap_with_gdiplus(hbmp, filename);
-    unsafe {
-        let _ = Gdi::DeleteObject(hbmp.into());
+fn run_bench(cli: &Cli, frames: u32) -> Result<()> {
+    let (rect, window) = resolve_target(cli)?;
+    let ext = Path::new(&cli.filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_else(|| fail("filename has no extension"));
+    let session = open_session(cli, window)?; // startup cost stays out of the per-frame numbers
+    let report = session.bench(rect, frames, ext, &cli.options)?;
+    let (_, _, w, h) = rect;
+    println!("{} frames of {w}x{h}, encoder .{ext}", report.frames);
+    print_phase("capture", &report.capture);
+    print_phase("encode", &report.encode);
+    println!("encoded size: {} bytes", report.encoded_bytes);
+    Ok(())
+}
+
+// capture and diff against the reference; a mismatch beyond the threshold exits 1.
+fn run_compare(cli: &Cli, compare: &Compare) -> Result<()> {
+    let (rect, window) = resolve_target(cli)?;
+    let report = open_session(cli, window)?.compare(
+        rect,
+        &cli.filename,
+        compare.diff_out.as_deref(),
+        &cli.options,
+    )?;
+    let pct = report.percent();
+    println!(
+        "difference: {pct:.4}% ({} of {} pixels, threshold {}%)",
+        report.differing,
+        report.width as u64 * report.height as u64,
+        compare.threshold
+    );
+    if pct > compare.threshold {
+        eprintln!("region does not match {}", cli.filename);
+        std::process::exit(EXIT_MISMATCH);
     }
-    result
+    Ok(())
 }
 
-fn main() -> windows::core::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    // Modes:
-    // 6 args: x y w h filename
-    // 3 args: flag + filename
-    // 2 args: filename => --primary
-    if args.len() == 6 {
-        // explicit rectangle
-        let x: i32 = args[1].parse().unwrap_or_else(|_| {
-            eprintln!("x must be an integer");
-            std::process::exit(1);
-        });
-        let y: i32 = args[2].parse().unwrap_or_else(|_| {
-            eprintln!("y must be an integer");
-            std::process::exit(1);
-        });
-        let w: i32 = args[3].parse().unwrap_or_else(|_| {
-            eprintln!("width must be an integer");
-            std::process::exit(1);
-        });
-        let h: i32 = args[4].parse().unwrap_or_else(|_| {
-            eprintln!("height must be an integer");
-            std::process::exit(1);
-        });
-        let filename = &args[5];
-        if w <= 0 || h <= 0 {
-            eprintln!("width and height must be > 0");
-            std::process::exit(1);
+// round-trip every encoder in memory; the first failure sets the exit code.
+fn run_selftest() -> Result<()> {
+    let checks = Session::new()?.selftest()?;
+    let total = checks.len();
+    let mut first_err = None;
+    for check in checks {
+        match check.result {
+            Ok(()) => println!(
+                "ok    {:<5} {:<11} {} bytes",
+                check.format, check.mime_type, check.bytes
+            ),
+            Err(e) => {
+                println!("FAIL  {:<5} {:<11} {e}", check.format, check.mime_type);
+                first_err.get_or_insert(e);
+            }
         }
-        capture_rectangle(x, y, w, h, filename)?;
-        return Ok(());
     }
-    // flag + filename OR just filename
-    let (mode, filename) = match args.len() {
-        3 => {
-            let flag = args[1].as_str();
-            let fname = args[2].as_str();
-            match flag {
-                "--full" => (ScreenMode::Virtual, fname),
-                "--primary" => (ScreenMode::Primary, fname),
-                _ => {
-                    usage();
-                    std::process::exit(1);
-                }
-            }
+    match first_err {
+        Some(e) => Err(e),
+        None => {
+            println!("selftest: all {total} encoders round-trip");
+            Ok(())
         }
-        2 => (ScreenMode::Primary, args[1].as_str()), // default to primary
+    }
+}
+
+// resolve everything a run would capture and write, print it, and stop before any BitBlt or
+// file write. shares monitor_jobs/resolve_target/sized_filename with the real runs.
+fn run_dry_run(cli: &Cli) -> Result<()> {
+    let mode = match (&cli.target, cli.compare.is_some(), cli.bench, cli.interval) {
+        (Target::EachMonitor { serial: true }, ..) => "each monitor, one after another",
+        (Target::EachMonitor { serial: false }, ..) => "each monitor, in parallel",
+        (_, true, ..) => "compare with reference",
+        (_, _, Some(_), _) => "benchmark",
+        (_, _, _, Some(_)) if cli.gif => "animated GIF",
+        (_, _, _, Some(_)) => "interval",
+        (Target::Rect(..), ..) if cli.dip => "rectangle (DIPs)",
+        (Target::Rect(..), ..) => "rectangle",
+        (Target::Screen(ScreenMode::Primary), ..) => "primary monitor",
+        (Target::Screen(ScreenMode::Virtual), ..) => "virtual desktop",
+        (Target::Screen(_), ..) => "monitor at point",
+        (Target::MonitorName(_), ..) => "monitor by name",
+        (Target::AroundCursor(..), ..) => "around cursor",
+        (
+            Target::Window {
+                dwm_thumbnail: true,
+                ..
+            },
+            ..,
+        ) => "window (DWM thumbnail)",
+        (
+            Target::Window {
+                monitor: Some(_), ..
+            },
+            ..,
+        ) => "window on monitor",
+        (Target::Window { .. }, ..) => "window",
+        (Target::StdinCoords, ..) => unreachable!("rejected while parsing"),
+    };
+    println!("mode: {mode}");
+    let jobs = match cli.target {
+        Target::EachMonitor { .. } => monitor_jobs(cli)?,
         _ => {
-            usage();
-            std::process::exit(1);
+            let (rect, _) = resolve_target(cli)?;
+            let file = if cli.temp {
+                let ext = cli.options.format.as_deref().unwrap_or("png");
+                let dir = env::temp_dir();
+                format!("{} (new gdip_snapshot_*.{ext} file)", dir.display())
+            } else if cli.base64 {
+                let uri = if cli.data_uri { "data: URI" } else { "base64" };
+                format!("stdout ({uri})")
+            } else if cli.compare.is_some() {
+                format!("{} (reference, read only)", cli.filename)
+            } else if cli.bench.is_some() {
+                "nothing saved".into()
+            } else if cli.interval.is_some() && !cli.gif && !is_pipe_path(&cli.filename) {
+                let frames = match cli.count {
+                    Some(n) => format!("{n} frames"),
+                    None => "until stopped".into(),
+                };
+                format!(
+                    "{}, ... ({frames})",
+                    numbered_filename(&sized_filename(cli, rect), 1)
+                )
+            } else {
+                sized_filename(cli, rect)
+            };
+            vec![(rect, file)]
         }
     };
-    let (x, y, w, h) = screen_rect(mode);
-    if w <= 0 || h <= 0 {
-        eprintln!("Detected non-positive screen size: {}x{}", w, h);
-        std::process::exit(1);
+    for ((x, y, w, h), file) in &jobs {
+        println!("capture: {x},{y} {w}x{h} -> {file}");
+    }
+    if let Some(diff_out) = cli.compare.as_ref().and_then(|c| c.diff_out.as_deref()) {
+        println!("diff image: {diff_out}");
+    }
+    // the encoder that would be used: the diff image's for --compare, else the output's.
+    // numbering and --append-dimensions never touch the extension
+    let encoded = match &cli.compare {
+        Some(compare) => compare.diff_out.clone(),
+        None if cli.temp || cli.base64 => Some(format!(
+            "capture.{}",
+            cli.options.format.as_deref().unwrap_or("png")
+        )),
+        None => Some(cli.filename.clone()),
+    };
+    match encoded {
+        Some(file) => println!("encoder: {}", encoder_format(&file, &cli.options)?),
+        None => println!("encoder: none (nothing is written)"),
     }
-    capture_rectangle(x, y, w, h, filename)?;
     Ok(())
 }
+
+// version, build target and what this machine's GDI/GDI+ look like, for bug reports.
+fn print_version() {
+    println!("gdip_snapshot {}", env!("CARGO_PKG_VERSION"));
+    println!("target: {}", env!("TARGET"));
+    println!("capture backends: GDI BitBlt (DXGI not compiled in)");
+    match gdiplus_version() {
+        Ok(v) => println!("GDI+: {v}"),
+        Err(e) => println!("GDI+: unavailable ({e})"),
+    }
+    println!("monitors: {}", unsafe { GetSystemMetrics(SM_CMONITORS) });
+    println!(
+        "remote session: {}",
+        if is_remote_session() { "yes" } else { "no" }
+    );
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {e}");
+        if let SnapshotError::NoEncodersInstalled = e {
+            eprintln!("hint: this system has no GDI+ image codecs (common in stripped-down");
+            eprintln!("      containers); retry with --no-gdiplus and a .bmp output file");
+        }
+        std::process::exit(exit_code(&e));
+    }
+    if interrupted() {
+        // the frame in progress was finished and the GIF flushed before run() returned
+        eprintln!("interrupted");
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+}
+
+fn run() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.iter().skip(1).any(|a| a == "--version" || a == "-V") {
+        print_version();
+        return Ok(());
+    }
+    if args.iter().skip(1).any(|a| a == "--selftest") {
+        return run_selftest();
+    }
+    let mut cli = parse_args(&args);
+    if cli.dry_run {
+        return run_dry_run(&cli);
+    }
+    if let Target::StdinCoords = cli.target {
+        return run_stdin_coords(&cli.options);
+    }
+    if let Target::EachMonitor { serial } = cli.target {
+        return run_each_monitor(&cli, serial);
+    }
+    if let Some(compare) = &cli.compare {
+        return run_compare(&cli, compare);
+    }
+    if let Some(frames) = cli.bench {
+        return run_bench(&cli, frames);
+    }
+    if let Some(interval) = cli.interval {
+        return run_interval(&cli, interval);
+    }
+    let ((x, y, w, h), window) = resolve_target(&cli)?;
+    if cli.base64 {
+        return capture_to_base64(&cli, (x, y, w, h), window);
+    }
+    if cli.temp {
+        return capture_to_temp(&mut cli, (x, y, w, h), window);
+    }
+    cli.filename = sized_filename(&cli, (x, y, w, h));
+    capture_single(&cli, (x, y, w, h), window)?;
+    if cli.options.verbose || cli.json {
+        report_dimensions(&cli, (x, y, w, h))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rect_needs_exactly_four_integers() {
+        assert_eq!(parse_rect("10, -20,300,400"), Some((10, -20, 300, 400)));
+        assert_eq!(parse_rect("1,2,3"), None);
+        assert_eq!(parse_rect("1,2,3,4,5"), None);
+        assert_eq!(parse_rect("1,2,x,4"), None);
+        assert_eq!(parse_rect(""), None);
+    }
+
+    #[test]
+    fn corners_normalize_and_reject_empty_or_overflowing_spans() {
+        assert_eq!(
+            corners_rect((1200, 800, 100, 50)),
+            Some((100, 50, 1100, 750))
+        );
+        assert_eq!(corners_rect((0, 0, 100, 100)), Some((0, 0, 100, 100)));
+        assert_eq!(corners_rect((5, 0, 5, 100)), None);
+        assert_eq!(corners_rect((i32::MIN, 0, i32::MAX, 10)), None);
+    }
+
+    #[test]
+    fn rop_names_and_hex_codes_combine() {
+        assert_eq!(parse_rop("srccopy|CAPTUREBLT"), Some(0x40CC_0020));
+        assert_eq!(parse_rop(" 0x00330008 "), Some(0x0033_0008));
+        assert_eq!(parse_rop("NOTSRCCOPY|0X40000000"), Some(0x4033_0008));
+        assert_eq!(parse_rop("SRCCOPY|BOGUS"), None);
+        assert_eq!(parse_rop("0xZZ"), None);
+        assert_eq!(parse_rop(""), None);
+    }
+
+    #[test]
+    fn coords_lines_keep_spaces_in_filenames() {
+        assert_eq!(
+            parse_coords_line("  -10 20 300 400 my shot.png "),
+            Ok(((-10, 20, 300, 400), "my shot.png"))
+        );
+        assert_eq!(
+            parse_coords_line("1 2 x 4 a.png"),
+            Err("width must be an integer".into())
+        );
+        assert_eq!(
+            parse_coords_line("1 2 0 4 a.png"),
+            Err("width and height must be > 0".into())
+        );
+        assert_eq!(parse_coords_line("1 2 3 4"), Err("missing filename".into()));
+        assert_eq!(parse_coords_line(""), Err("x must be an integer".into()));
+    }
+
+    #[test]
+    fn json_strings_escape_quotes_backslashes_and_controls() {
+        assert_eq!(json_string(r"C:\shots\a.png"), r#""C:\\shots\\a.png""#);
+        assert_eq!(json_string("say \"hi\""), r#""say \"hi\"""#);
+        assert_eq!(json_string("a\nb\u{1}"), r#""a\u000ab\u0001""#);
+        assert_eq!(json_string("ünïcode"), "\"ünïcode\"");
+    }
+
+    #[test]
+    fn suffix_goes_before_the_extension() {
+        assert_eq!(suffixed_filename("shot.png", "0001"), "shot_0001.png");
+        assert_eq!(suffixed_filename("shot", "2x1"), "shot_2x1");
+        assert_eq!(
+            suffixed_filename("out.d/a.b.jpg", "0002"),
+            Path::new("out.d").join("a.b_0002.jpg").to_string_lossy()
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// Monitor enumeration and one-shot capture of every monitor for --each-monitor.
+
+use std::mem::size_of;
+use std::sync::mpsc;
+use std::thread;
+
+use windows::Win32::Foundation::{E_FAIL, LPARAM, RECT};
+use windows::Win32::Graphics::Gdi::{
+    EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW,
+};
+use windows::core::{BOOL, Error, HRESULT};
+
+use crate::{CaptureRequest, CaptureResult, Result, Session, SnapshotError, monitor_rect};
+
+type Rect = (i32, i32, i32, i32); // x, y, w, h
+
+unsafe extern "system" fn collect_monitor(
+    hmon: HMONITOR,
+    _hdc: HDC,
+    _clip: *mut RECT,
+    data: LPARAM,
+) -> BOOL {
+    let found = unsafe { &mut *(data.0 as *mut Vec<HMONITOR>) };
+    found.push(hmon);
+    true.into()
+}
+
+fn monitor_handles() -> Result<Vec<HMONITOR>> {
+    let mut found: Vec<HMONITOR> = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(
+            None,
+            None,
+            Some(collect_monitor),
+            LPARAM(&mut found as *mut _ as isize),
+        )
+        .ok()?;
+    }
+    Ok(found)
+}
+
+/// Rectangles (x, y, w, h) of all attached monitors, left to right then top to bottom.
+pub fn monitor_rects() -> Result<Vec<Rect>> {
+    let mut rects = monitor_handles()?
+        .into_iter()
+        .map(monitor_rect)
+        .collect::<Result<Vec<_>>>()?;
+    rects.sort_by_key(|&(x, y, _, _)| (x, y));
+    Ok(rects)
+}
+
+// GDI device name of a monitor, e.g. "\\.\DISPLAY1".
+fn device_name(hmon: HMONITOR) -> Result<String> {
+    let mut info = MONITORINFOEXW::default();
+    info.monitorInfo.cbSize = size_of::<MONITORINFOEXW>() as u32;
+    let ok = unsafe { GetMonitorInfoW(hmon, &mut info as *mut _ as *mut MONITORINFO) };
+    if !ok.as_bool() {
+        return Err(Error::new(HRESULT(E_FAIL.0), "GetMonitorInfoW failed").into());
+    }
+    let len = info.szDevice.iter().position(|&c| c == 0).unwrap_or(32);
+    Ok(String::from_utf16_lossy(&info.szDevice[..len]))
+}
+
+/// Device names (`\\.\DISPLAY1`, ...) and rectangles of all attached monitors, in the
+/// order of [`monitor_rects`]. Unlike positions and indices, a monitor's device name stays
+/// the same across hot-plugging and reboots.
+pub fn monitor_names() -> Result<Vec<(String, Rect)>> {
+    let mut monitors = monitor_handles()?
+        .into_iter()
+        .map(|hmon| Ok((device_name(hmon)?, monitor_rect(hmon)?)))
+        .collect::<Result<Vec<_>>>()?;
+    monitors.sort_by_key(|&(_, (x, y, _, _))| (x, y));
+    Ok(monitors)
+}
+
+/// Rectangle of the monitor whose device name is `name`, compared case-insensitively; the
+/// `\\.\` prefix may be left out. The error lists the names that do exist.
+pub fn monitor_by_name(name: &str) -> Result<Rect> {
+    let monitors = monitor_names()?;
+    let bare = |n: &str| n.trim_start_matches(r"\\.\").to_ascii_lowercase();
+    if let Some(&(_, rect)) = monitors.iter().find(|(n, _)| bare(n) == bare(name)) {
+        return Ok(rect);
+    }
+    let names: Vec<_> = monitors.iter().map(|(n, _)| n.as_str()).collect();
+    Err(SnapshotError::InvalidRegion(format!(
+        "no monitor named {name}; available: {}",
+        names.join(", ")
+    )))
+}
+
+/// Run each capture request, returning one result per request in input order. With
+/// `parallel`, every request gets its own thread, and each thread starts its own GDI+ session
+/// and screen DC, so no GDI or GDI+ handle crosses a thread boundary.
+pub fn capture_monitors(jobs: &[CaptureRequest], parallel: bool) -> Vec<Result<CaptureResult>> {
+    let capture = |request: &CaptureRequest| Session::new()?.capture_request(request);
+    if !parallel {
+        return jobs.iter().map(capture).collect();
+    }
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for (i, job) in jobs.iter().enumerate() {
+            let tx = tx.clone();
+            scope.spawn(move || {
+                // the receiver outlives the scope, so this send cannot fail
+                let _ = tx.send((i, capture(job)));
+            });
+        }
+    });
+    drop(tx);
+    let mut results: Vec<_> = rx.into_iter().collect();
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
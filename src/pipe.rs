@@ -0,0 +1,80 @@
+// Writing encoded frames to a named pipe (\\.\pipe\name) instead of a file.
+
+use std::io;
+
+use windows::Win32::Foundation::{
+    CloseHandle, ERROR_BROKEN_PIPE, ERROR_FILE_NOT_FOUND, ERROR_NO_DATA, ERROR_PIPE_BUSY,
+    GENERIC_WRITE, HANDLE,
+};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_NONE, OPEN_EXISTING, WriteFile,
+};
+use windows::core::{HRESULT, PCWSTR};
+
+use crate::{Result, wide};
+
+struct HandleGuard(HANDLE);
+impl Drop for HandleGuard {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.0);
+        }
+    }
+}
+
+/// Whether an output path names a pipe (`\\.\pipe\name`) rather than a file.
+pub fn is_pipe_path(path: &str) -> bool {
+    path.get(..9)
+        .is_some_and(|p| p.eq_ignore_ascii_case(r"\\.\pipe\"))
+}
+
+fn not_connected(msg: String) -> crate::SnapshotError {
+    io::Error::new(io::ErrorKind::NotConnected, msg).into()
+}
+
+/// Connect to the server end of `path` and write `bytes` to it in one message.
+pub(crate) fn write_to_pipe(path: &str, bytes: &[u8]) -> Result<()> {
+    let wpath = wide(path);
+    let handle = unsafe {
+        CreateFileW(
+            PCWSTR(wpath.as_ptr()),
+            GENERIC_WRITE.0,
+            FILE_SHARE_NONE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        )
+    }
+    .map_err(|e| match e.code() {
+        c if c == HRESULT::from_win32(ERROR_FILE_NOT_FOUND.0) => {
+            not_connected(format!("no process is listening on {path}"))
+        }
+        c if c == HRESULT::from_win32(ERROR_PIPE_BUSY.0) => {
+            not_connected(format!("{path} is busy serving another client"))
+        }
+        _ => e.into(),
+    })?;
+    let _guard = HandleGuard(handle);
+    let mut written = 0u32;
+    unsafe { WriteFile(handle, Some(bytes), Some(&mut written), None) }.map_err(|e| {
+        match e.code() {
+            c if c == HRESULT::from_win32(ERROR_NO_DATA.0)
+                || c == HRESULT::from_win32(ERROR_BROKEN_PIPE.0) =>
+            {
+                not_connected(format!(
+                    "the reader closed {path} before the frame was sent"
+                ))
+            }
+            _ => e.into(),
+        }
+    })?;
+    if written as usize != bytes.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            format!("short write to {path}: {written} of {} bytes", bytes.len()),
+        )
+        .into());
+    }
+    Ok(())
+}
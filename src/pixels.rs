@@ -0,0 +1,281 @@
+// Direct pixel passes over capture DIB sections and GDI+ bitmaps.
+
+use std::mem::size_of;
+use std::ptr::null_mut;
+
+use windows::Win32::Foundation::E_FAIL;
+use windows::Win32::Graphics::Gdi;
+use windows::Win32::Graphics::GdiPlus;
+use windows::core::{Error, HRESULT};
+
+use crate::{ImgGuard, PIXEL_FORMAT_32BPP_ARGB, Result, gdip_check, image_size};
+
+/// Pixel memory of a DIB section: bottom-up rows of `stride` bytes.
+pub(crate) struct DibBits {
+    ptr: *mut u8,
+    pub width: i32,
+    pub height: i32,
+    pub stride: usize,
+    pub bpp: u16,
+}
+
+impl DibBits {
+    // all rows, including padding; valid while the owning HBITMAP lives.
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.stride * self.height as usize) }
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.stride * self.height as usize) }
+    }
+}
+
+pub(crate) fn dib_bits(hbmp: Gdi::HBITMAP) -> Result<DibBits> {
+    let mut ds = Gdi::DIBSECTION::default();
+    let n = unsafe {
+        Gdi::GetObjectW(
+            hbmp.into(),
+            size_of::<Gdi::DIBSECTION>() as i32,
+            Some(&mut ds as *mut _ as *mut _),
+        )
+    };
+    if n as usize != size_of::<Gdi::DIBSECTION>() || ds.dsBm.bmBits.is_null() {
+        return Err(Error::new(HRESULT(E_FAIL.0), "bitmap is not a DIB section").into());
+    }
+    Ok(DibBits {
+        ptr: ds.dsBm.bmBits as *mut u8,
+        width: ds.dsBm.bmWidth,
+        height: ds.dsBm.bmHeight.abs(),
+        stride: ds.dsBm.bmWidthBytes as usize,
+        bpp: ds.dsBm.bmBitsPixel,
+    })
+}
+
+/// Lock a GDI+ bitmap as 32bpp ARGB and hand `f` the top-down rows (BGRA bytes), the width,
+/// height and stride. With `write`, changes are stored back when the lock is released.
+pub(crate) fn lock_bits<R>(
+    img: &ImgGuard,
+    write: bool,
+    f: impl FnOnce(&mut [u8], i32, i32, usize) -> R,
+) -> Result<R> {
+    let (w, h) = image_size(img)?;
+    let rect = GdiPlus::Rect {
+        X: 0,
+        Y: 0,
+        Width: w,
+        Height: h,
+    };
+    let mut mode = GdiPlus::ImageLockModeRead.0 as u32;
+    if write {
+        mode |= GdiPlus::ImageLockModeWrite.0 as u32;
+    }
+    let bmp = img.0 as *mut GdiPlus::GpBitmap;
+    let mut data = GdiPlus::BitmapData::default();
+    unsafe {
+        gdip_check(
+            GdiPlus::GdipBitmapLockBits(bmp, &rect, mode, PIXEL_FORMAT_32BPP_ARGB, &mut data),
+            "GdipBitmapLockBits",
+        )?;
+        let stride = data.Stride as usize; // positive for the ARGB lock format
+        let buf = std::slice::from_raw_parts_mut(data.Scan0 as *mut u8, stride * h as usize);
+        let result = f(buf, w, h, stride);
+        gdip_check(
+            GdiPlus::GdipBitmapUnlockBits(bmp, &mut data),
+            "GdipBitmapUnlockBits",
+        )?;
+        Ok(result)
+    }
+}
+
+/// Copy a 32bpp DIB section into a new 32bpp ARGB GDI+ bitmap, keeping the DIB's alpha
+/// channel (GdipCreateBitmapFromHBITMAP always discards it). The DIB is only read.
+pub(crate) fn argb_bitmap_from_dib(bits: &DibBits) -> Result<ImgGuard> {
+    if bits.bpp != 32 {
+        return Err(Error::new(HRESULT(E_FAIL.0), "alpha needs a 32bpp capture").into());
+    }
+    let (w, h, src_stride) = (bits.width, bits.height, bits.stride);
+    let mut bmp = null_mut();
+    unsafe {
+        gdip_check(
+            GdiPlus::GdipCreateBitmapFromScan0(w, h, 0, PIXEL_FORMAT_32BPP_ARGB, None, &mut bmp),
+            "GdipCreateBitmapFromScan0",
+        )?;
+    }
+    let img = ImgGuard(bmp as *mut GdiPlus::GpImage);
+    let src = bits.bytes();
+    lock_bits(&img, true, |dst, _, _, dst_stride| {
+        let row = w as usize * 4;
+        for y in 0..h as usize {
+            let s = (h as usize - 1 - y) * src_stride; // DIB rows are bottom-up
+            dst[y * dst_stride..y * dst_stride + row].copy_from_slice(&src[s..s + row]);
+        }
+    })?;
+    Ok(img)
+}
+
+/// If every pixel of an ARGB bitmap has alpha 0, as GDI leaves it, make them all opaque and
+/// return true; an alpha channel with any content is left alone.
+pub(crate) fn fill_empty_alpha(img: &ImgGuard) -> Result<bool> {
+    lock_bits(img, true, |buf, w, h, stride| {
+        let rows = || (0..h as usize).map(|y| y * stride..y * stride + w as usize * 4);
+        if rows().any(|r| buf[r].chunks_exact(4).any(|px| px[3] != 0)) {
+            return false;
+        }
+        for r in rows() {
+            for px in buf[r].chunks_exact_mut(4) {
+                px[3] = 255;
+            }
+        }
+        true
+    })
+}
+
+/// Whether every pixel of a 24 or 32bpp DIB is pure black (alpha aside), as a blocked or
+/// failed screen copy comes back. Stops at the first pixel that isn't.
+pub(crate) fn is_all_black(bits: &DibBits) -> bool {
+    let px = bits.bpp as usize / 8;
+    let (row, stride) = (bits.width as usize * px, bits.stride);
+    let buf = bits.bytes();
+    (0..bits.height as usize).all(|y| {
+        buf[y * stride..y * stride + row]
+            .chunks_exact(px)
+            .all(|p| p[..3] == [0, 0, 0])
+    })
+}
+
+/// Make pixels within `tolerance` (Euclidean RGB distance) of `key` fully transparent and
+/// every other pixel opaque. Works on an ARGB bitmap in place.
+pub(crate) fn color_key(img: &ImgGuard, key: (u8, u8, u8), tolerance: f64) -> Result<()> {
+    let limit = tolerance * tolerance;
+    lock_bits(img, true, |buf, w, h, stride| {
+        for y in 0..h as usize {
+            for px in buf[y * stride..y * stride + w as usize * 4].chunks_exact_mut(4) {
+                // BGRA
+                let db = px[0] as f64 - key.2 as f64;
+                let dg = px[1] as f64 - key.1 as f64;
+                let dr = px[2] as f64 - key.0 as f64;
+                px[3] = if dr * dr + dg * dg + db * db <= limit {
+                    0
+                } else {
+                    255
+                };
+            }
+        }
+    })
+}
+
+/// Sharpen a GDI+ bitmap in place with a 3x3 unsharp-mask kernel: each pixel gains `amount`
+/// times its difference from the average of its four neighbours. Edge pixels and alpha are
+/// left unchanged.
+pub(crate) fn sharpen(img: &ImgGuard, amount: f64) -> Result<()> {
+    lock_bits(img, true, |buf, w, h, stride| {
+        let (w, h) = (w as usize, h as usize);
+        if w < 3 || h < 3 {
+            return;
+        }
+        let src = buf.to_vec(); // kernel reads the unsharpened pixels
+        for y in 1..h - 1 {
+            for x in 1..w - 1 {
+                let i = y * stride + x * 4;
+                for c in 0..3 {
+                    let center = src[i + c] as f64;
+                    let around = src[i - 4 + c] as f64
+                        + src[i + 4 + c] as f64
+                        + src[i - stride + c] as f64
+                        + src[i + stride + c] as f64;
+                    let v = center + amount * (4.0 * center - around) / 4.0;
+                    buf[i + c] = v.round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    })
+}
+
+/// Box-blur `region` (x, y, w, h, inside the image) of a GDI+ bitmap in place: a horizontal
+/// then a vertical pass averaging `radius` pixels on each side. Samples stay within the region
+/// so nothing outside it bleeds in; alpha is blurred like the color channels.
+pub(crate) fn box_blur(img: &ImgGuard, region: (i32, i32, i32, i32), radius: u32) -> Result<()> {
+    let (rx, ry, rw, rh) = (
+        region.0 as usize,
+        region.1 as usize,
+        region.2 as usize,
+        region.3 as usize,
+    );
+    let r = radius as usize;
+    lock_bits(img, true, |buf, _, _, stride| {
+        // one line of `len` pixels starting at byte `start`, `step` bytes apart
+        let mut blur_line = |start: usize, step: usize, len: usize| {
+            let line: Vec<[u8; 4]> = (0..len)
+                .map(|i| {
+                    let p = start + i * step;
+                    [buf[p], buf[p + 1], buf[p + 2], buf[p + 3]]
+                })
+                .collect();
+            for i in 0..len {
+                let (lo, hi) = (i.saturating_sub(r), (i + r).min(len - 1));
+                let mut sum = [0u32; 4];
+                for px in &line[lo..=hi] {
+                    for c in 0..4 {
+                        sum[c] += px[c] as u32;
+                    }
+                }
+                let n = (hi - lo + 1) as u32;
+                let p = start + i * step;
+                for c in 0..4 {
+                    buf[p + c] = ((sum[c] + n / 2) / n) as u8;
+                }
+            }
+        };
+        for y in ry..ry + rh {
+            blur_line(y * stride + rx * 4, 4, rw);
+        }
+        for x in rx..rx + rw {
+            blur_line(ry * stride + x * 4, stride, rh);
+        }
+    })
+}
+
+/// Write a DIB section as an uncompressed BMP file without GDI+ (no encoders needed).
+pub(crate) fn write_bmp(bits: &mut DibBits, filename: &str) -> Result<()> {
+    let (w, h, bpp) = (bits.width, bits.height, bits.bpp);
+    let data = bits.bytes_mut();
+    let offset = 14 + 40u32;
+    let mut file = Vec::with_capacity(offset as usize + data.len());
+    // BITMAPFILEHEADER
+    file.extend(b"BM");
+    file.extend((offset + data.len() as u32).to_le_bytes());
+    file.extend([0u8; 4]);
+    file.extend(offset.to_le_bytes());
+    // BITMAPINFOHEADER; positive height = bottom-up rows, same as the DIB
+    file.extend(40u32.to_le_bytes());
+    file.extend(w.to_le_bytes());
+    file.extend(h.to_le_bytes());
+    file.extend(1u16.to_le_bytes());
+    file.extend(bpp.to_le_bytes());
+    file.extend(0u32.to_le_bytes()); // BI_RGB
+    file.extend((data.len() as u32).to_le_bytes());
+    file.extend([0u8; 16]); // resolution and palette counts
+    file.extend_from_slice(data);
+    std::fs::write(filename, file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Options;
+    use crate::backend::fake_image;
+
+    #[test]
+    fn all_black_check_ignores_alpha_and_finds_any_color() {
+        let bmp = fake_image(8, 8, &Options::default());
+        let mut bits = dib_bits(bmp.0).unwrap();
+        assert!(!is_all_black(&bits));
+        bits.bytes_mut()
+            .chunks_exact_mut(4)
+            .for_each(|px| px.copy_from_slice(&[0, 0, 0, 255]));
+        assert!(is_all_black(&bits));
+        bits.bytes_mut()[4 * 63 + 2] = 1; // red, last pixel
+        assert!(!is_all_black(&bits));
+    }
+}
@@ -0,0 +1,90 @@
+// Encoder round-trip smoke test for --selftest: a 1x1 capture is encoded by every installed
+// encoder into memory and decoded again, so GDI+ startup, encoder enumeration and the
+// save/load stream paths are all exercised without writing files.
+
+use std::ptr::null_mut;
+
+use windows::Win32::Foundation::{E_FAIL, HGLOBAL};
+use windows::Win32::Graphics::{Gdi, GdiPlus};
+use windows::Win32::System::Com::STREAM_SEEK_SET;
+use windows::Win32::System::Com::StructuredStorage::CreateStreamOnHGlobal;
+use windows::core::{Error, GUID, HRESULT};
+
+use crate::backend::CaptureBackend;
+use crate::{
+    ImgGuard, Options, Result, SnapshotError, encode_image, gdip_check, image_size,
+    installed_encoders, prepare_image,
+};
+
+/// Outcome for one encoder in [`crate::Session::selftest`].
+pub struct FormatCheck {
+    /// First extension the encoder registers, lowercase ("png", "jpg", ...).
+    pub format: String,
+    pub mime_type: String,
+    /// Encoded size of the test image; 0 if encoding failed.
+    pub bytes: usize,
+    /// Whether the image encoded, decoded again and came back 1x1.
+    pub result: Result<()>,
+}
+
+// decode an in-memory image and return its size.
+pub(crate) fn decoded_size(bytes: &[u8]) -> Result<(i32, i32)> {
+    unsafe {
+        let stream = CreateStreamOnHGlobal(HGLOBAL::default(), true)?;
+        stream
+            .Write(bytes.as_ptr().cast(), bytes.len() as u32, None)
+            .ok()?;
+        stream.Seek(0, STREAM_SEEK_SET, None)?;
+        let mut img = null_mut();
+        gdip_check(
+            GdiPlus::GdipLoadImageFromStream(&stream, &mut img),
+            "GdipLoadImageFromStream",
+        )?;
+        image_size(&ImgGuard(img))
+    }
+}
+
+fn round_trip(img: &ImgGuard, clsid: &GUID) -> (usize, Result<()>) {
+    let bytes = match encode_image(img, clsid, None) {
+        Ok(bytes) => bytes,
+        Err(e) => return (0, Err(e)),
+    };
+    let result = decoded_size(&bytes).and_then(|(w, h)| match (w, h) {
+        (1, 1) => Ok(()),
+        _ => Err(Error::new(
+            HRESULT(E_FAIL.0),
+            format!("decoded as {w}x{h}, expected 1x1"),
+        )
+        .into()),
+    });
+    (bytes.len(), result)
+}
+
+pub(crate) fn run(backend: &dyn CaptureBackend) -> Result<Vec<FormatCheck>> {
+    let encoders = installed_encoders()?;
+    if encoders.is_empty() {
+        return Err(SnapshotError::NoEncodersInstalled);
+    }
+    let options = Options::default();
+    let hbmp = backend.capture((0, 0, 1, 1), &options)?;
+    let img = prepare_image(hbmp, &options, false);
+    unsafe {
+        let _ = Gdi::DeleteObject(hbmp.into());
+    }
+    let img = img?;
+    Ok(encoders
+        .iter()
+        .map(|info| {
+            // "*.JPG;*.JPEG;..." -> "jpg"
+            let first = info.extensions.split(';').next().unwrap_or("");
+            let format = first.trim().trim_start_matches("*.").to_ascii_lowercase();
+            let (bytes, result) = round_trip(&img, &info.clsid);
+            FormatCheck {
+                format,
+                mime_type: info.mime_type.clone(),
+                bytes,
+                result,
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,99 @@
+// Raw frame streaming for library consumers: repeated BitBlt into one reused DIB section.
+
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+
+use windows::Win32::Foundation::E_FAIL;
+use windows::Win32::Graphics::Gdi;
+use windows::core::{Error, HRESULT};
+
+use crate::{
+    BitDepth, BitmapGuard, DcGuard, Result, ScreenDcGuard, SelectGuard, SnapshotError, dib_stride,
+    make_dib_section, screen_raster_op,
+};
+
+/// Capture `region` (x, y, w, h) every `interval` and hand each frame to `on_frame` until it
+/// returns [`ControlFlow::Break`]. Nothing is encoded or written. A region with a zero or
+/// negative size is an [`SnapshotError::InvalidRegion`].
+///
+/// `on_frame` receives the pixel bits with the frame's width and height. The layout is that of
+/// a bottom-up 32bpp `BI_RGB` DIB: rows run from the bottom of the region to the top, each
+/// `width * 4` bytes long (the stride; 32bpp rows need no padding), and every pixel is four
+/// bytes in B, G, R, X order. The fourth byte is not meaningful alpha. The slice points into
+/// a DIB section that is reused for the next frame, so copy anything you want to keep.
+///
+/// A frame that takes longer than `interval` to capture and process is followed by the next
+/// one straight away; frames are never skipped to catch up. In a Remote Desktop session the
+/// frames are copied without `CAPTUREBLT`, as for [`crate::capture_rectangle`].
+pub fn capture_stream(
+    region: (i32, i32, i32, i32),
+    interval: Duration,
+    mut on_frame: impl FnMut(&[u8], i32, i32) -> ControlFlow<()>,
+) -> Result<()> {
+    let (x, y, w, h) = region;
+    if w <= 0 || h <= 0 {
+        return Err(SnapshotError::InvalidRegion(format!(
+            "stream region {w}x{h} is empty"
+        )));
+    }
+    let len = dib_stride(w, BitDepth::Bits32)
+        .checked_mul(h.unsigned_abs() as usize)
+        .ok_or_else(|| {
+            SnapshotError::InvalidRegion(format!("stream region {w}x{h} is too large"))
+        })?;
+    let raster_op = screen_raster_op(false);
+    unsafe {
+        let hdc_screen = Gdi::GetDC(None);
+        if hdc_screen.0.is_null() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "GetDC failed").into());
+        }
+        let _screen_guard = ScreenDcGuard(hdc_screen);
+        let mem_dc = Gdi::CreateCompatibleDC(Some(hdc_screen));
+        if mem_dc.0.is_null() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "CreateCompatibleDC failed").into());
+        }
+        let _mem_guard = DcGuard(mem_dc);
+        let (hbmp, bits) = make_dib_section(w, h, hdc_screen, BitDepth::Bits32)?;
+        let _bmp_guard = BitmapGuard(hbmp);
+        let old = Gdi::SelectObject(mem_dc, hbmp.into());
+        if old.is_invalid() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "SelectObject failed").into());
+        }
+        let _sel_guard = SelectGuard { dc: mem_dc, old };
+
+        loop {
+            let started = Instant::now();
+            Gdi::BitBlt(mem_dc, 0, 0, w, h, Some(hdc_screen), x, y, raster_op)?;
+            // make sure GDI has finished writing the bits before reading them directly
+            let _ = Gdi::GdiFlush();
+            let frame = std::slice::from_raw_parts(bits, len);
+            if on_frame(frame, w, h).is_break() {
+                return Ok(());
+            }
+            if let Some(rest) = interval.checked_sub(started.elapsed()) {
+                std::thread::sleep(rest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_or_negative_regions_are_rejected() {
+        for region in [
+            (0, 0, 0, 10),
+            (0, 0, 10, 0),
+            (0, 0, 10, -10),
+            (0, 0, -10, 10),
+        ] {
+            let result = capture_stream(region, Duration::ZERO, |_, _, _| ControlFlow::Break(()));
+            assert!(
+                matches!(result, Err(SnapshotError::InvalidRegion(_))),
+                "{region:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// Window lookup (title / HWND / foreground) and window-relative rectangles.
+
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use windows::Win32::Foundation::{E_FAIL, HWND, LPARAM, POINT, RECT};
+use windows::Win32::Graphics::Dwm::{DWMWA_EXTENDED_FRAME_BOUNDS, DwmGetWindowAttribute};
+use windows::Win32::Graphics::Gdi::ClientToScreen;
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, GetClientRect, GetForegroundWindow, GetWindowRect, GetWindowTextW, IsWindow,
+    IsWindowVisible,
+};
+use windows::core::{BOOL, Error, HRESULT};
+
+use crate::{Result, SnapshotError};
+
+/// How to pick the window for a window capture.
+#[derive(Clone)]
+pub enum WindowSelector {
+    /// First visible top-level window whose title contains this text (case-insensitive).
+    Title(String),
+    /// An explicit window handle.
+    Hwnd(isize),
+    /// Whatever window currently has focus.
+    Foreground,
+}
+
+struct TitleSearch {
+    needle: String,
+    found: Vec<HWND>,
+    all: bool, // keep going after the first match
+}
+
+unsafe extern "system" fn match_title(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let search = unsafe { &mut *(lparam.0 as *mut TitleSearch) };
+    if !unsafe { IsWindowVisible(hwnd) }.as_bool() {
+        return true.into();
+    }
+    let mut buf = [0u16; 512];
+    let len = unsafe { GetWindowTextW(hwnd, &mut buf) };
+    if len <= 0 {
+        return true.into();
+    }
+    let title = String::from_utf16_lossy(&buf[..len as usize]).to_lowercase();
+    if title.contains(&search.needle) {
+        search.found.push(hwnd);
+        if !search.all {
+            return false.into(); // stop enumerating
+        }
+    }
+    true.into()
+}
+
+/// First visible top-level window whose title contains `substr` (case-insensitive).
+pub fn find_window_by_title(substr: &str) -> Option<HWND> {
+    search_titles(substr, false).first().copied()
+}
+
+/// Every visible top-level window whose title contains `substr` (case-insensitive), in
+/// z-order.
+pub fn find_windows_by_title(substr: &str) -> Vec<HWND> {
+    search_titles(substr, true)
+}
+
+fn search_titles(substr: &str, all: bool) -> Vec<HWND> {
+    let mut search = TitleSearch {
+        needle: substr.to_lowercase(),
+        found: Vec::new(),
+        all,
+    };
+    // EnumWindows reports an error when the callback stops early, so ignore its result.
+    let _ = unsafe { EnumWindows(Some(match_title), LPARAM(&mut search as *mut _ as isize)) };
+    search.found
+}
+
+/// Poll every 100 ms for a visible window whose title contains `substr` until one appears or
+/// `timeout` elapses ([`SnapshotError::Timeout`]).
+pub fn wait_for_window(substr: &str, timeout: Duration) -> Result<HWND> {
+    let started = Instant::now();
+    loop {
+        if let Some(hwnd) = find_window_by_title(substr) {
+            return Ok(hwnd);
+        }
+        let waited = started.elapsed();
+        if waited >= timeout {
+            return Err(SnapshotError::Timeout(format!(
+                "no visible window title contained \"{substr}\" within {:.1} s",
+                timeout.as_secs_f64()
+            )));
+        }
+        std::thread::sleep((timeout - waited).min(Duration::from_millis(100)));
+    }
+}
+
+/// Resolve a selector to a live window handle. A window that can't be found is an
+/// [`SnapshotError::InvalidRegion`]: the capture target doesn't exist.
+pub fn find_window(sel: &WindowSelector) -> Result<HWND> {
+    let hwnd = match sel {
+        WindowSelector::Title(t) => find_window_by_title(t).ok_or_else(|| {
+            SnapshotError::InvalidRegion(format!("no visible window title contains \"{t}\""))
+        })?,
+        WindowSelector::Hwnd(h) => HWND(*h as *mut _),
+        WindowSelector::Foreground => unsafe { GetForegroundWindow() },
+    };
+    if hwnd.0.is_null() || !unsafe { IsWindow(Some(hwnd)) }.as_bool() {
+        return Err(SnapshotError::InvalidRegion("not a valid window".into()));
+    }
+    Ok(hwnd)
+}
+
+/// Screen rectangle (x, y, w, h) of a window: its client area, or the visible frame.
+pub fn window_rect(hwnd: HWND, client_only: bool) -> Result<(i32, i32, i32, i32)> {
+    let mut r = RECT::default();
+    if client_only {
+        unsafe { GetClientRect(hwnd, &mut r)? };
+        let mut origin = POINT::default();
+        if !unsafe { ClientToScreen(hwnd, &mut origin) }.as_bool() {
+            return Err(Error::new(HRESULT(E_FAIL.0), "ClientToScreen failed").into());
+        }
+        return Ok((origin.x, origin.y, r.right - r.left, r.bottom - r.top));
+    }
+    // the DWM frame bounds exclude the invisible resize borders GetWindowRect reports
+    let dwm = unsafe {
+        DwmGetWindowAttribute(
+            hwnd,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut r as *mut RECT as *mut _,
+            size_of::<RECT>() as u32,
+        )
+    };
+    if dwm.is_err() {
+        unsafe { GetWindowRect(hwnd, &mut r)? };
+    }
+    Ok((r.left, r.top, r.right - r.left, r.bottom - r.top))
+}
+
+/// Place a sub-rectangle (x, y, w, h) relative to `outer`'s origin, translating it to screen
+/// coordinates. Negative x/y anchor from the right/bottom edge. Errors if it doesn't fit.
+pub fn sub_rect(
+    outer: (i32, i32, i32, i32),
+    region: (i32, i32, i32, i32),
+) -> Result<(i32, i32, i32, i32)> {
+    let (ox, oy, ow, oh) = outer;
+    let (x, y, w, h) = region;
+    let rx = if x < 0 { ow + x } else { x };
+    let ry = if y < 0 { oh + y } else { y };
+    let fits = |start: i32, len: i32, outer: i32| {
+        len > 0 && start >= 0 && start.checked_add(len).is_some_and(|end| end <= outer)
+    };
+    if !fits(rx, w, ow) || !fits(ry, h, oh) {
+        return Err(SnapshotError::InvalidRegion(format!(
+            "region {x},{y},{w},{h} does not fit within the {ow}x{oh} window"
+        )));
+    }
+    Ok((ox + rx, oy + ry, w, h))
+}
+
+/// Grow (or, for negative `n`, shrink) a rectangle by `n` pixels on every side. Fails with
+/// [`SnapshotError::InvalidRegion`] if shrinking leaves nothing or the padded rectangle lies
+/// beyond the `i32` range.
+pub fn pad_rect(rect: (i32, i32, i32, i32), n: i32) -> Result<(i32, i32, i32, i32)> {
+    let (x, y, w, h) = rect;
+    let grow = |v: i32| n.checked_mul(2).and_then(|n2| v.checked_add(n2));
+    let padded = (x.checked_sub(n), y.checked_sub(n), grow(w), grow(h));
+    let (Some(x), Some(y), Some(w), Some(h)) = padded else {
+        return Err(SnapshotError::InvalidRegion(format!(
+            "padding {n} takes the window past the coordinate range"
+        )));
+    };
+    if w <= 0 || h <= 0 {
+        return Err(SnapshotError::InvalidRegion(
+            "padding shrinks the window to nothing".into(),
+        ));
+    }
+    Ok((x, y, w, h))
+}
+
+/// Parse a window handle as printed by Spy++ and friends: `0x1A2B3C`, bare hex with letters
+/// or a leading zero (`001A2B3C`), or decimal (`1715004`, or negative for sign-extended
+/// handles). Handles are pointer-sized, so values up to the full pointer width are accepted;
+/// zero and values that overflow are rejected. Whether the handle names a live window is
+/// checked later by [`find_window`].
+pub fn parse_hwnd(s: &str) -> Option<isize> {
+    let s = s.trim();
+    let hex = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => Some(hex),
+        None if s.starts_with('0') || s.bytes().any(|b| b.is_ascii_alphabetic()) => Some(s),
+        None => None,
+    };
+    let value = match hex {
+        // bit patterns: 0xFFFFFFFF8A0B1234 is a valid (sign-extended) handle
+        Some(hex) if !hex.starts_with(['+', '-']) => usize::from_str_radix(hex, 16).ok()? as isize,
+        Some(_) => return None,
+        None => s.parse().ok()?,
+    };
+    (value != 0).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_handles_parse_with_and_without_prefix() {
+        assert_eq!(parse_hwnd("0x1A2B3C"), Some(0x1A2B3C));
+        assert_eq!(parse_hwnd("0X1a2b3c"), Some(0x1A2B3C));
+        assert_eq!(parse_hwnd("1A2B3C"), Some(0x1A2B3C));
+        assert_eq!(parse_hwnd("001A2B3C"), Some(0x1A2B3C)); // Spy++ style
+        assert_eq!(parse_hwnd("00012345"), Some(0x12345)); // leading zero => hex
+    }
+
+    #[test]
+    fn decimal_handles_parse() {
+        assert_eq!(parse_hwnd("1715004"), Some(1715004));
+        assert_eq!(parse_hwnd("-1946152396"), Some(-1946152396));
+    }
+
+    #[test]
+    fn pointer_wide_handles_are_not_truncated() {
+        let h = parse_hwnd("0x7FF6A0B1C2D3").unwrap();
+        assert_eq!(h as u64, 0x7FF6_A0B1_C2D3);
+        let sign_extended = parse_hwnd("0xFFFFFFFF8A0B1234").unwrap();
+        assert_eq!(sign_extended as usize, 0xFFFF_FFFF_8A0B_1234);
+    }
+
+    #[test]
+    fn overflowing_and_invalid_handles_are_rejected() {
+        assert_eq!(parse_hwnd("0x1FFFFFFFFFFFFFFFF"), None);
+        assert_eq!(parse_hwnd("99999999999999999999"), None);
+        assert_eq!(parse_hwnd("0x"), None);
+        assert_eq!(parse_hwnd("0x-12"), None);
+        assert_eq!(parse_hwnd("0"), None);
+        assert_eq!(parse_hwnd("0x0"), None);
+        assert_eq!(parse_hwnd("notepad"), None);
+        assert_eq!(parse_hwnd(""), None);
+    }
+
+    #[test]
+    fn padding_grows_shrinks_and_rejects_overflow() {
+        assert_eq!(pad_rect((10, 10, 100, 50), 5).unwrap(), (5, 5, 110, 60));
+        assert_eq!(pad_rect((10, 10, 100, 50), -5).unwrap(), (15, 15, 90, 40));
+        for n in [-25, i32::MAX, i32::MIN] {
+            assert!(
+                matches!(
+                    pad_rect((10, 10, 100, 50), n),
+                    Err(SnapshotError::InvalidRegion(_))
+                ),
+                "{n}"
+            );
+        }
+    }
+
+    #[test]
+    fn sub_regions_must_fit_the_window() {
+        let window = (100, 50, 800, 600);
+        assert_eq!(
+            sub_rect(window, (10, 20, 30, 40)).unwrap(),
+            (110, 70, 30, 40)
+        );
+        assert_eq!(
+            sub_rect(window, (-30, -40, 30, 40)).unwrap(),
+            (870, 610, 30, 40)
+        );
+        for region in [(790, 0, 20, 10), (0, 0, 0, 10), (10, 0, i32::MAX, 1)] {
+            assert!(
+                matches!(
+                    sub_rect(window, region),
+                    Err(SnapshotError::InvalidRegion(_))
+                ),
+                "{region:?}"
+            );
+        }
+    }
+}